@@ -0,0 +1,64 @@
+use std::time::{Duration, Instant};
+
+use smithay_client_toolkit::reexports::client::protocol::wl_buffer::WlBuffer;
+
+/// Frames of an animated wallpaper after its first one, which is
+/// the wl_buffer of the wallpaper itself, all in its shm pool
+pub struct Animation {
+    buffers: Vec<WlBuffer>,
+    delays: Vec<Duration>,
+}
+
+impl Animation {
+    pub fn new(buffers: Vec<WlBuffer>, delays: Vec<Duration>) -> Animation {
+        assert_eq!(buffers.len() + 1, delays.len());
+        Animation { buffers, delays }
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.delays.len()
+    }
+
+    pub fn delay(&self, frame: usize) -> Duration {
+        self.delays[frame]
+    }
+
+    /// Buffer of a frame other than the first one
+    pub fn buffer(&self, frame: usize) -> &WlBuffer {
+        &self.buffers[frame - 1]
+    }
+}
+
+impl Drop for Animation {
+    fn drop(&mut self) {
+        for buffer in &self.buffers {
+            buffer.destroy();
+        }
+    }
+}
+
+/// Animation playing on an output
+pub struct Playback {
+    pub frame: usize,
+    pub next_frame: Instant,
+}
+
+impl Playback {
+    pub fn start(animation: &Animation) -> Playback {
+        Playback {
+            frame: 0,
+            next_frame: Instant::now() + animation.delay(0),
+        }
+    }
+
+    /// Move to the next frame, keeping to the timing of the animation
+    /// unless fallen behind by more than a frame
+    pub fn advance(&mut self, animation: &Animation, now: Instant) {
+        self.frame = (self.frame + 1) % animation.frame_count();
+        let delay = animation.delay(self.frame);
+        self.next_frame += delay;
+        if self.next_frame < now {
+            self.next_frame = now + delay;
+        }
+    }
+}
@@ -1,10 +1,19 @@
-use std::fmt::Write as _;
+use std::{fmt::Write as _, path::PathBuf};
 
-use anyhow::bail;
-use clap::{Parser, ValueEnum};
+use anyhow::{bail, Context};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use log::warn;
+use serde::Deserialize;
 
-use crate::Levels;
+use crate::{
+    Levels,
+    config::{MAX_ANIMATION_MEMORY, Options},
+    control::Request,
+    image::{Color, FitMode},
+    schedule::{ClockTime, ScheduleOptions},
+    slideshow::SlideshowOrder,
+    transition::{TransitionEasing, TransitionMode},
+};
 
 #[derive(Parser)]
 #[command(author, version, long_about = None, about = "\
@@ -59,6 +68,50 @@ pub struct Cli {
     /// Adjusts brightness, eg. -b=-60 (default: 0)
     #[arg(short, long)]
     pub brightness: Option<i32>,
+    /// How images with a different size than the output are fit to it
+    /// (default: fill)
+    #[arg(long)]
+    pub fit: Option<FitMode>,
+    /// Color of the area not covered by the image with --fit=fit or center
+    /// and of the transparent parts of images, eg. '#1e1e2e'
+    /// (default: #000000)
+    #[arg(long)]
+    pub background_color: Option<Color>,
+    /// Seconds between switching the images of workspaces with
+    /// a slideshow directory: wallpaper_dir/output/workspace_name/
+    /// (default: 600)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub slideshow_interval: Option<u64>,
+    /// Order of the images in slideshows (default: sorted)
+    #[arg(long)]
+    pub slideshow_order: Option<SlideshowOrder>,
+    /// Animation when switching wallpapers, not supported with --gpu
+    /// (default: none)
+    #[arg(long)]
+    pub transition: Option<TransitionMode>,
+    /// Length of the transition in milliseconds (default: 300)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub transition_duration: Option<u64>,
+    /// Pace of the transition (default: linear)
+    #[arg(long)]
+    pub transition_easing: Option<TransitionEasing>,
+    /// Show only the first frame of animated images with more frames
+    /// (default: 300)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub animation_max_frames: Option<u64>,
+    /// Show only the first frame of animated images whose frames would
+    /// take more memory in MiB (default: 256)
+    #[arg(long, value_parser = clap::value_parser!(u64)
+        .range(1..=MAX_ANIMATION_MEMORY))]
+    pub animation_max_memory: Option<u64>,
+    /// Keep showing the same image of the random pool on a workspace
+    /// for the session instead of picking again on every visit
+    #[arg(long)]
+    pub random_stable: bool,
+    /// Number of images of the random pool kept decoded in memory,
+    /// the others are loaded when picked (default: 4)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    pub random_cache_size: Option<u64>,
     /// wl_buffer pixel format (default: auto)
     #[arg(long)]
     pub pixelformat: Option<PixelFormat>,
@@ -71,62 +124,208 @@ pub struct Cli {
     /// list output names and make-model-serials and exit
     #[arg(long)]
     pub list_outputs: bool,
+    /// make the already running instance on the same Wayland display exit
+    /// and take its place
+    #[arg(long)]
+    pub replace: bool,
+    /// shell command to run when the wallpaper of an output changes,
+    /// see the README for its environment variables
+    #[arg(long, value_name = "COMMAND")]
+    pub on_change: Option<String>,
+    /// also write the shown wallpapers as processed and resized PNG images
+    /// to $XDG_RUNTIME_DIR/multibg-wayland/<output>.png
+    #[arg(long)]
+    pub export_png: bool,
+    /// latitude to show the time of day variants of wallpapers, such as
+    /// 1.night.jpg, by the position of the sun, positive to the north
+    #[arg(long, allow_negative_numbers = true)]
+    pub latitude: Option<f64>,
+    /// longitude to show the time of day variants of wallpapers
+    /// by the position of the sun, positive to the east
+    #[arg(long, allow_negative_numbers = true)]
+    pub longitude: Option<f64>,
+    /// start of the day on the local clock without latitude and longitude
+    /// (default: 07:00)
+    #[arg(long, value_name = "HH:MM")]
+    pub day_start: Option<ClockTime>,
+    /// start of the dusk (default: 19:00)
+    #[arg(long, value_name = "HH:MM")]
+    pub dusk_start: Option<ClockTime>,
+    /// start of the night (default: 21:00)
+    #[arg(long, value_name = "HH:MM")]
+    pub night_start: Option<ClockTime>,
+    /// configuration file
+    /// (default: $XDG_CONFIG_HOME/multibg-wayland/config.toml if exists)
+    #[arg(long)]
+    pub config: Option<String>,
     /// directory with: wallpaper_dir/output/workspace_name.{jpg|png|...}
-    #[arg(default_value = ".")]
-    pub wallpaper_dir: String,
+    /// (default: wallpaper-dir from the configuration file or .)
+    pub wallpaper_dir: Option<String>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Send a command to the running instance and print its reply
+    Msg(MsgArgs),
+}
+
+#[derive(Args)]
+pub struct MsgArgs {
+    /// print the reply as received, a single line of JSON
+    #[arg(long)]
+    pub raw: bool,
+    #[command(subcommand)]
+    pub request: MsgRequest,
+}
+
+#[derive(Subcommand)]
+pub enum MsgRequest {
+    /// Reload the configuration, all wallpapers and outputs
+    Reload,
+    /// Print the outputs, their visible workspaces and wallpapers
+    State,
+    /// Temporarily show an image on a workspace or on all workspaces
+    /// of an output
+    Set {
+        output: String,
+        path: PathBuf,
+        /// only on this workspace instead of all workspaces
+        #[arg(long)]
+        workspace: Option<String>,
+        /// keep showing the image after restarts until cleared
+        #[arg(long)]
+        persist: bool,
+    },
+    /// Clear the temporary images set with the set command
+    Clear {
+        /// only on this output instead of all outputs
+        output: Option<String>,
+        /// only on this workspace instead of all workspaces
+        #[arg(long)]
+        workspace: Option<String>,
+    },
+    /// Stop following workspace switches
+    Pause,
+    /// Continue following workspace switches
+    Resume,
+    /// Pause if following workspace switches, resume if paused
+    TogglePause,
+    /// Print events as lines of JSON until the running instance exits
+    Subscribe,
+}
+
+impl MsgRequest {
+    pub fn into_request(self) -> anyhow::Result<Request> {
+        Ok(match self {
+            MsgRequest::Reload => Request::Reload,
+            MsgRequest::State => Request::State,
+            MsgRequest::Set { output, path, workspace, persist } => {
+                Request::Set {
+                    output,
+                    workspace,
+                    // Relative to our working directory, not the daemon's
+                    path: path.canonicalize().with_context(|| format!(
+                        "Failed to resolve absolute path for {path:?}"))?,
+                    persist,
+                }
+            },
+            MsgRequest::Clear { output, workspace } => {
+                Request::Clear { output, workspace }
+            },
+            MsgRequest::Pause => Request::Pause,
+            MsgRequest::Resume => Request::Resume,
+            MsgRequest::TogglePause => Request::TogglePause,
+            MsgRequest::Subscribe => Request::Subscribe,
+        })
+    }
 }
 
 impl Cli {
-    pub fn levels(&self) -> anyhow::Result<Option<Levels>> {
-        let has_levels = self.level_output_min.is_some()
-            || self.level_output_max.is_some()
-            || self.level_input_min.is_some()
-            || self.level_input_max.is_some();
+    pub fn schedule_options(&self) -> ScheduleOptions {
+        ScheduleOptions {
+            latitude: self.latitude,
+            longitude: self.longitude,
+            day_start: self.day_start,
+            dusk_start: self.dusk_start,
+            night_start: self.night_start,
+        }
+    }
+
+    pub fn options(&self) -> anyhow::Result<Options> {
+        let mut options = Options {
+            level_output_min: self.level_output_min,
+            level_output_max: self.level_output_max,
+            level_input_min: self.level_input_min,
+            level_input_max: self.level_input_max,
+            pixelformat: self.pixelformat,
+            gpu: self.gpu.then_some(true),
+            fit: self.fit,
+            background_color: self.background_color,
+            slideshow_interval: self.slideshow_interval,
+            slideshow_order: self.slideshow_order,
+            transition: self.transition,
+            transition_duration: self.transition_duration,
+            transition_easing: self.transition_easing,
+            animation_max_frames: self.animation_max_frames,
+            animation_max_memory: self.animation_max_memory,
+            random_stable: self.random_stable.then_some(true),
+            random_cache_size: self.random_cache_size,
+        };
+        let has_levels = options.has_levels();
         let has_brightness_contrast = self.brightness.is_some()
             || self.contrast.is_some();
         if has_levels && has_brightness_contrast {
             bail!("Options --level-* are mutually exclusive with \
                 legacy options --brightness and --contrast");
         } else if has_levels {
-            let input_min = self.level_input_min.unwrap_or(0.0);
-            let input_max = self.level_input_max.unwrap_or(1.0);
-            let output_min = self.level_output_min.unwrap_or(0.0);
-            let output_max = self.level_output_max.unwrap_or(1.0);
-            if input_min == 0.0 && input_max == 1.0
-                && output_min == 0.0 && output_max == 1.0
-            {
-                return Ok(None)
-            }
-            if !(0.0..=input_max).contains(&input_min) {
-                bail!("Option --level-input-min must be \
-                    a fraction in range [0.0, level-input-max]");
-            }
-            if !(input_min..=1.0).contains(&input_max) {
-                bail!("Option --level-input-max must be \
-                    a fraction in range [level-input-min, 1.0]");
-            }
-            if !(0.0..=1.0).contains(&output_min) {
-                bail!("Option --level-output-min must be \
-                    a fraction in range [0.0, 1.0]");
-            }
-            if !(0.0..=1.0).contains(&output_max) {
-                bail!("Option --level-output-max must be \
-                    a fraction in range [0.0, 1.0]");
-            }
-            Ok(Some(Levels { input_min, input_max, output_min, output_max }))
+            options.levels(|key| format!("Option --{key}"))?;
         } else if has_brightness_contrast {
             let brightness = self.brightness.unwrap_or(0);
             let contrast = self.contrast.unwrap_or(0.0);
-            if brightness == 0 && contrast == 0.0 {
-                return Ok(None)
+            if brightness != 0 || contrast != 0.0 {
+                let levels = Levels::from_legacy(brightness, contrast);
+                warn_brightness_contrast(brightness, contrast, &levels);
+                options.level_output_min = Some(levels.output_min);
+                options.level_output_max = Some(levels.output_max);
+                options.level_input_min = Some(levels.input_min);
+                options.level_input_max = Some(levels.input_max);
             }
-            let levels = Levels::from_legacy(brightness, contrast);
-            warn_brightness_contrast(brightness, contrast, &levels);
-            Ok(Some(levels))
-        } else {
-            Ok(None)
         }
+        Ok(options)
+    }
+}
+
+pub fn levels(
+    input_min: f32,
+    input_max: f32,
+    output_min: f32,
+    output_max: f32,
+    option_name: impl Fn(&str) -> String,
+) -> anyhow::Result<Option<Levels>> {
+    if input_min == 0.0 && input_max == 1.0
+        && output_min == 0.0 && output_max == 1.0
+    {
+        return Ok(None)
+    }
+    if !(0.0..=input_max).contains(&input_min) {
+        bail!("{} must be a fraction in range [0.0, level-input-max]",
+            option_name("level-input-min"));
+    }
+    if !(input_min..=1.0).contains(&input_max) {
+        bail!("{} must be a fraction in range [level-input-min, 1.0]",
+            option_name("level-input-max"));
+    }
+    if !(0.0..=1.0).contains(&output_min) {
+        bail!("{} must be a fraction in range [0.0, 1.0]",
+            option_name("level-output-min"));
+    }
+    if !(0.0..=1.0).contains(&output_max) {
+        bail!("{} must be a fraction in range [0.0, 1.0]",
+            option_name("level-output-max"));
     }
+    Ok(Some(Levels { input_min, input_max, output_min, output_max }))
 }
 
 fn warn_brightness_contrast(
@@ -160,7 +359,8 @@ fn warn_brightness_contrast(
     warn!("{}", w);
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum PixelFormat {
     Auto,
     Baseline,
@@ -6,6 +6,7 @@ mod niri2604;
 mod sway;
 
 use std::{
+    collections::{HashMap, HashSet},
     env,
     os::unix::ffi::OsStrExt,
     process::Command,
@@ -32,6 +33,11 @@ pub struct OutputInfo {
 }
 
 impl Compositor {
+    /// Whether workspaces are stacked vertically instead of side by side
+    pub fn has_vertical_workspaces(self) -> bool {
+        matches!(self, Compositor::Niri)
+    }
+
     pub fn from_env() -> Option<Compositor> {
         Compositor::from_xdg_desktop_var("XDG_SESSION_DESKTOP")
             .or_else(|| Compositor::from_xdg_desktop_var("XDG_CURRENT_DESKTOP"))
@@ -230,13 +236,7 @@ impl ConnectionTask {
             .into_iter()
             .find(|w| w.output == output)
         {
-            self.tx
-                .send(WorkspaceVisible {
-                    output: workspace.output,
-                    workspace_name: workspace.workspace_name,
-                    workspace_number: workspace.workspace_number,
-                })
-                .unwrap();
+            self.tx.send(workspace).unwrap();
 
             self.waker.wake();
         }
@@ -246,13 +246,7 @@ impl ConnectionTask {
         for workspace in self.interface
             .request_visible_workspaces().into_iter()
         {
-            self.tx
-                .send(WorkspaceVisible {
-                    output: workspace.output,
-                    workspace_name: workspace.workspace_name,
-                    workspace_number: workspace.workspace_number
-                })
-                .unwrap();
+            self.tx.send(workspace).unwrap();
 
             self.waker.wake();
         }
@@ -271,6 +265,8 @@ pub struct WorkspaceVisible {
     pub output: String,
     pub workspace_name: String,
     pub workspace_number: i32,
+    /// The workspace has windows
+    pub occupied: bool,
 }
 
 #[derive(Deserialize)]
@@ -316,3 +312,86 @@ fn make_model_serial(make: &str, model: &str, serial: &str) -> String {
         .collect::<Vec<&str>>()
         .join(" ")
 }
+
+/// Workspace of each niri window, shared by the niri IPC versions
+/// to tell which workspaces have windows
+#[derive(Default)]
+struct NiriWindows {
+    workspaces: HashMap<u64, u64>,
+}
+
+enum NiriWindowChange {
+    All(Vec<(u64, Option<u64>)>),
+    OpenedOrChanged(u64, Option<u64>),
+    Closed(u64),
+}
+
+impl NiriWindows {
+    fn new(windows: Vec<(u64, Option<u64>)>) -> NiriWindows {
+        let mut niri_windows = NiriWindows::default();
+        niri_windows.apply(NiriWindowChange::All(windows));
+        niri_windows
+    }
+
+    fn is_occupied(&self, workspace_id: u64) -> bool {
+        self.workspaces.values().any(|&id| id == workspace_id)
+    }
+
+    /// Returns the ids of the workspaces which got their first window
+    /// or lost their last one
+    fn apply(&mut self, change: NiriWindowChange) -> Vec<u64> {
+        let occupied_before = self.occupied();
+        match change {
+            NiriWindowChange::All(windows) => {
+                self.workspaces = windows.into_iter()
+                    .filter_map(|(id, workspace_id)| Some((id, workspace_id?)))
+                    .collect();
+            },
+            NiriWindowChange::OpenedOrChanged(id, Some(workspace_id)) => {
+                self.workspaces.insert(id, workspace_id);
+            },
+            NiriWindowChange::OpenedOrChanged(id, None)
+            | NiriWindowChange::Closed(id) => {
+                self.workspaces.remove(&id);
+            },
+        }
+        let occupied_after = self.occupied();
+        occupied_before.symmetric_difference(&occupied_after)
+            .copied()
+            .collect()
+    }
+
+    fn occupied(&self) -> HashSet<u64> {
+        self.workspaces.values().copied().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn niri_workspace_occupancy() {
+        let mut windows = NiriWindows::new(vec![(1, Some(10)), (2, None)]);
+        assert!(windows.is_occupied(10));
+        assert!(!windows.is_occupied(20));
+
+        // Moving the only window empties its workspace
+        let mut changed = windows.apply(
+            NiriWindowChange::OpenedOrChanged(1, Some(20)));
+        changed.sort();
+        assert_eq!(changed, [10, 20]);
+
+        // A second window on the same workspace changes nothing
+        let changed = windows.apply(
+            NiriWindowChange::OpenedOrChanged(2, Some(20)));
+        assert!(changed.is_empty());
+        let changed = windows.apply(NiriWindowChange::Closed(1));
+        assert!(changed.is_empty());
+        assert_eq!(windows.apply(NiriWindowChange::Closed(2)), [20]);
+        assert!(!windows.is_occupied(20));
+
+        assert_eq!(windows.apply(NiriWindowChange::All(vec![(3, Some(30))])),
+            [30]);
+    }
+}
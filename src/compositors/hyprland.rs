@@ -1,14 +1,16 @@
 // https://wiki.hyprland.org/IPC/
 
 use std::{
+    collections::HashSet,
     env,
     io::{Read, Write},
     os::unix::net::UnixStream,
     path::PathBuf,
 };
 
-use log::debug;
-use serde::Deserialize;
+use anyhow::Context;
+use log::{debug, error};
+use serde::{de::DeserializeOwned, Deserialize};
 
 use super::{
     CompositorInterface,
@@ -86,10 +88,14 @@ impl CompositorInterface for HyprlandConnectionTask {
                     String::from_utf8_lossy(event_data),
                 );
                 if !has_workspacev2 && event_name == b"workspace" {
+                    let workspace_name = String::from_utf8(
+                        event_data.to_vec()
+                    ).unwrap();
                     event_sender.send(WorkspaceVisible {
                         output: active_monitor.clone(),
-                        workspace_name: String::from_utf8(event_data.to_vec())
-                            .unwrap(),
+                        occupied: occupied_workspaces()
+                            .contains(&workspace_name),
+                        workspace_name,
                         workspace_number: -1,
                     });
                 } else if event_name == b"workspacev2" {
@@ -109,6 +115,8 @@ impl CompositorInterface for HyprlandConnectionTask {
                     ).unwrap();
                     event_sender.send(WorkspaceVisible {
                         output: active_monitor.clone(),
+                        occupied: occupied_workspaces()
+                            .contains(&workspace_name),
                         workspace_name,
                         workspace_number,
                     });
@@ -120,6 +128,10 @@ impl CompositorInterface for HyprlandConnectionTask {
                         .unwrap();
                 } else if event_name == b"moveworkspace"
                     || event_name == b"renameworkspace"
+                    // Windows may have left or entered the visible workspaces
+                    || event_name == b"openwindow"
+                    || event_name == b"closewindow"
+                    || event_name == b"movewindowv2"
                 {
                     let current_state = current_state();
                     for workspace in current_state.visible_workspaces {
@@ -156,33 +168,60 @@ fn socket_dir_path() -> PathBuf {
 fn current_state() -> CurrentState {
     let mut active_monitor = String::new();
     let mut visible_workspaces = Vec::new();
+    let occupied_workspaces = occupied_workspaces();
     for monitor in monitors() {
         if monitor.focused {
             active_monitor = monitor.name.clone();
         }
+        let occupied = occupied_workspaces
+            .contains(&monitor.active_workspace.name);
         visible_workspaces.push(WorkspaceVisible {
             output: monitor.name,
             workspace_name: monitor.active_workspace.name,
             workspace_number: monitor.active_workspace.id.unwrap_or(-1),
+            occupied,
         });
     }
     CurrentState { active_monitor, visible_workspaces }
 }
 
+// Names of the workspaces with windows from a single workspaces request,
+// none of them are taken as occupied if it fails
+fn occupied_workspaces() -> HashSet<String> {
+    match workspaces() {
+        Ok(workspaces) => workspaces.into_iter()
+            .filter(|workspace| workspace.windows > 0)
+            .map(|workspace| workspace.name)
+            .collect(),
+        Err(e) => {
+            error!("{e:#}");
+            HashSet::new()
+        },
+    }
+}
+
 fn monitors() -> Vec<Monitor> {
+    request("monitors").unwrap_or_else(|e| panic!("{e:#}"))
+}
+
+fn workspaces() -> anyhow::Result<Vec<Workspace>> {
+    request("workspaces")
+}
+
+fn request<T: DeserializeOwned>(command: &str) -> anyhow::Result<T> {
     let mut socket = socket_dir_path();
     socket.push(".socket.sock");
     let mut connection = UnixStream::connect(socket)
-        .expect("Failed to connect to Hyprland requests socket");
-    connection.write_all(b"j/monitors")
-        .expect("Failed to send Hyprland monitors requests");
+        .context("Failed to connect to Hyprland requests socket")?;
+    connection.write_all(format!("j/{command}").as_bytes())
+        .with_context(|| format!("Failed to send Hyprland {command} request"))?;
     let mut buf = Vec::with_capacity(2000);
     // This socket .socket.sock for hyprctl-like requests
     // only allows one round trip with a single or batched commands
-    let read = connection.read_to_end(&mut buf)
-        .expect("Failed to receive Hyprland monitors response");
-    serde_json::from_slice(&buf[..read])
-        .expect("Failed to parse Hyprland monitors response")
+    let read = connection.read_to_end(&mut buf).with_context(||
+        format!("Failed to receive Hyprland {command} response"))?;
+    serde_json::from_slice(&buf[..read]).with_context(||
+        format!("Failed to parse Hyprland {command} response"))
 }
 
 struct CurrentState {
@@ -201,6 +240,12 @@ struct Monitor {
     focused: bool,
 }
 
+#[derive(Deserialize)]
+struct Workspace {
+    name: String,
+    windows: u32,
+}
+
 #[derive(Deserialize)]
 struct ActiveWorkspace {
     id: Option<i32>,
@@ -13,6 +13,8 @@ use super::{
     CompositorInterface,
     EventSender,
     make_model_serial,
+    NiriWindowChange,
+    NiriWindows,
     OutputInfo,
     WorkspaceVisible,
 };
@@ -27,6 +29,7 @@ impl NiriConnectionTask {
 
 impl CompositorInterface for NiriConnectionTask {
     fn request_visible_workspaces(&mut self) -> Vec<WorkspaceVisible> {
+        let windows = request_windows();
         request_workspaces().into_iter()
             .filter(|w| w.is_active)
             .map(|workspace| WorkspaceVisible {
@@ -34,6 +37,7 @@ impl CompositorInterface for NiriConnectionTask {
                 workspace_name: workspace.name
                     .unwrap_or_else(|| format!("{}", workspace.idx)),
                 workspace_number: workspace.idx.into(),
+                occupied: windows.is_occupied(workspace.id),
             })
             .collect()
     }
@@ -53,26 +57,59 @@ impl CompositorInterface for NiriConnectionTask {
 
     fn subscribe_event_loop(self, event_sender: EventSender) {
         let mut workspaces_state = request_workspaces();
+        let mut windows_state = request_windows();
         let mut callback = request_event_stream();
         while let Ok(event) = callback() {
-            match event {
+            let change = match event {
                 Event::WorkspaceActivated { id, focused: _ } => {
                     debug!("Niri event: workspace id {id} activated");
                     let visible_workspace =
-                        find_workspace(&workspaces_state, id);
+                        find_workspace(&workspaces_state, &windows_state, id);
                     event_sender.send(visible_workspace);
+                    continue
                 },
                 Event::WorkspacesChanged { workspaces } => {
                     debug!("Niri event: workspaces changed: {workspaces:?}");
-                    workspaces_state = workspaces
+                    workspaces_state = workspaces;
+                    continue
                 },
-                _ => {},
+                Event::WindowsChanged { windows } => NiriWindowChange::All(
+                    windows.iter()
+                        .map(|window| (window.id, window.workspace_id))
+                        .collect()
+                ),
+                Event::WindowOpenedOrChanged { window } => {
+                    NiriWindowChange::OpenedOrChanged(
+                        window.id,
+                        window.workspace_id,
+                    )
+                },
+                Event::WindowClosed { id } => NiriWindowChange::Closed(id),
+                _ => continue,
+            };
+            for workspace_id in windows_state.apply(change) {
+                let is_active = workspaces_state.iter().any(|workspace| {
+                    workspace.id == workspace_id && workspace.is_active
+                });
+                if is_active {
+                    debug!("Niri event: workspace id {workspace_id} \
+                        occupied: {}", windows_state.is_occupied(workspace_id));
+                    event_sender.send(find_workspace(
+                        &workspaces_state,
+                        &windows_state,
+                        workspace_id,
+                    ));
+                }
             }
         }
     }
 }
 
-fn find_workspace(workspaces: &[Workspace], id: u64) -> WorkspaceVisible {
+fn find_workspace(
+    workspaces: &[Workspace],
+    windows: &NiriWindows,
+    id: u64,
+) -> WorkspaceVisible {
     let workspace = workspaces.iter()
         .find(|workspace| workspace.id == id)
         .unwrap_or_else(|| panic!("Unknown niri workspace id {id}"));
@@ -80,7 +117,8 @@ fn find_workspace(workspaces: &[Workspace], id: u64) -> WorkspaceVisible {
         .unwrap_or_else(|| format!("{}", workspace.idx));
     let workspace_number: i32 = workspace.idx.into();
     let output = workspace.output.clone().unwrap_or_default();
-    WorkspaceVisible { output, workspace_name, workspace_number }
+    let occupied = windows.is_occupied(id);
+    WorkspaceVisible { output, workspace_name, workspace_number, occupied }
 }
 
 fn request_event_stream() -> impl FnMut() -> Result<Event, io::Error> {
@@ -106,6 +144,21 @@ fn request_workspaces() -> Vec<Workspace> {
     workspaces
 }
 
+fn request_windows() -> NiriWindows {
+    let response = Socket::connect()
+        .expect("failed to connect to niri socket")
+        .send(Request::Windows)
+        .expect("failed to send niri ipc request")
+        .0
+        .expect("niri window query failed");
+    let Response::Windows(windows) = response else {
+        panic!("unexpected response from niri");
+    };
+    NiriWindows::new(windows.iter()
+        .map(|window| (window.id, window.workspace_id))
+        .collect())
+}
+
 fn request_outputs() -> HashMap<String, Output> {
     let response = Socket::connect()
         .expect("failed to connect to niri socket")
@@ -1,4 +1,6 @@
-use swayipc::{Connection, Event, EventType, WorkspaceChange};
+use swayipc::{
+    Connection, Event, EventType, Node, WindowChange, WorkspaceChange,
+};
 
 use super::{
     CompositorInterface,
@@ -31,6 +33,8 @@ impl CompositorInterface for SwayConnectionTask {
                 output: workspace.output,
                 workspace_name: workspace.name,
                 workspace_number: workspace.num,
+                // Tiling and floating windows
+                occupied: !workspace.focus.is_empty(),
             })
             .collect()
     }
@@ -49,22 +53,41 @@ impl CompositorInterface for SwayConnectionTask {
             .collect()
     }
 
-    fn subscribe_event_loop(self, event_sender: EventSender) {
-        let event_stream = self.sway_conn
-            .subscribe([EventType::Workspace]).unwrap();
+    fn subscribe_event_loop(mut self, event_sender: EventSender) {
+        // The subscribed connection only receives events
+        let event_stream = Connection::new().unwrap()
+            .subscribe([EventType::Workspace, EventType::Window]).unwrap();
         for event_result in event_stream {
-            let event = event_result.unwrap();
-            let Event::Workspace(workspace_event) = event else {
-                continue;
-            };
-            if let WorkspaceChange::Focus = workspace_event.change {
-                let current_workspace = workspace_event.current.unwrap();
-                event_sender.send(WorkspaceVisible {
-                    output: current_workspace.output.unwrap(),
-                    workspace_name: current_workspace.name.unwrap(),
-                    workspace_number: current_workspace.num.unwrap(),
-                });
+            match event_result.unwrap() {
+                Event::Workspace(workspace_event) => {
+                    if let WorkspaceChange::Focus = workspace_event.change {
+                        let current_workspace =
+                            workspace_event.current.unwrap();
+                        event_sender.send(WorkspaceVisible {
+                            occupied: has_windows(&current_workspace),
+                            output: current_workspace.output.unwrap(),
+                            workspace_name: current_workspace.name.unwrap(),
+                            workspace_number: current_workspace.num.unwrap(),
+                        });
+                    }
+                },
+                Event::Window(window_event) => {
+                    // Windows may have left or entered the visible workspaces
+                    if matches!(window_event.change, WindowChange::New
+                        | WindowChange::Close
+                        | WindowChange::Move
+                    ) {
+                        for workspace in self.request_visible_workspaces() {
+                            event_sender.send(workspace);
+                        }
+                    }
+                },
+                _ => continue,
             }
         }
     }
 }
+
+fn has_windows(workspace: &Node) -> bool {
+    !workspace.nodes.is_empty() || !workspace.floating_nodes.is_empty()
+}
@@ -0,0 +1,640 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use log::{debug, error};
+use serde::Deserialize;
+
+use crate::{
+    cli::{levels, PixelFormat},
+    hook::ChangeHook,
+    image::{
+        Color, ColorTransform, FitMode, Levels, WallpaperFile,
+        slideshow_wallpaper_files, wallpaper_file,
+    },
+    pattern::match_specificity,
+    schedule::{ClockTime, Schedule, ScheduleOptions},
+    slideshow::SlideshowOrder,
+    transition::{TransitionEasing, TransitionMode},
+};
+
+// Example:
+//
+// wallpaper-dir = "~/my_wallpapers"
+// level-output-max = 0.6
+// transition = "crossfade"
+// on-change = "notify-send \"$MULTIBG_OUTPUT: $MULTIBG_WALLPAPER\""
+//
+// [workspaces]
+// _default = "~/Pictures/default.jpg"
+//
+// [outputs.HDMI-A-1]
+// level-output-max = 0.8
+// gpu = true
+//
+// [outputs.HDMI-A-1.workspaces]
+// 1 = "~/Pictures/mountains.jpg"
+// browser = "/usr/share/backgrounds/forest.png"
+// 2 = { path = "~/Pictures/logo.png", fit = "center" }
+
+/// Limit of animation-max-memory in MiB, keeping the offsets
+/// of the animation frames in shm pools within i32
+pub const MAX_ANIMATION_MEMORY: u64 = 2047;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct ConfigFile {
+    wallpaper_dir: Option<String>,
+    on_change: Option<String>,
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+    day_start: Option<ClockTime>,
+    dusk_start: Option<ClockTime>,
+    night_start: Option<ClockTime>,
+    level_output_min: Option<f32>,
+    level_output_max: Option<f32>,
+    level_input_min: Option<f32>,
+    level_input_max: Option<f32>,
+    pixelformat: Option<PixelFormat>,
+    gpu: Option<bool>,
+    fit: Option<FitMode>,
+    background_color: Option<Color>,
+    slideshow_interval: Option<u64>,
+    slideshow_order: Option<SlideshowOrder>,
+    transition: Option<TransitionMode>,
+    transition_duration: Option<u64>,
+    transition_easing: Option<TransitionEasing>,
+    animation_max_frames: Option<u64>,
+    animation_max_memory: Option<u64>,
+    random_stable: Option<bool>,
+    random_cache_size: Option<u64>,
+    #[serde(default)]
+    workspaces: BTreeMap<String, WorkspaceConfigFile>,
+    #[serde(default)]
+    outputs: BTreeMap<String, OutputConfigFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct OutputConfigFile {
+    level_output_min: Option<f32>,
+    level_output_max: Option<f32>,
+    level_input_min: Option<f32>,
+    level_input_max: Option<f32>,
+    pixelformat: Option<PixelFormat>,
+    gpu: Option<bool>,
+    fit: Option<FitMode>,
+    background_color: Option<Color>,
+    slideshow_interval: Option<u64>,
+    slideshow_order: Option<SlideshowOrder>,
+    transition: Option<TransitionMode>,
+    transition_duration: Option<u64>,
+    transition_easing: Option<TransitionEasing>,
+    animation_max_frames: Option<u64>,
+    animation_max_memory: Option<u64>,
+    random_stable: Option<bool>,
+    random_cache_size: Option<u64>,
+    #[serde(default)]
+    workspaces: BTreeMap<String, WorkspaceConfigFile>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum WorkspaceConfigFile {
+    Path(String),
+    Table(WorkspaceTableConfigFile),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct WorkspaceTableConfigFile {
+    path: String,
+    fit: Option<FitMode>,
+    background_color: Option<Color>,
+}
+
+impl WorkspaceConfigFile {
+    fn resolve(self, base_dir: &Path) -> WorkspaceConfig {
+        match self {
+            WorkspaceConfigFile::Path(path) => WorkspaceConfig {
+                path: expand_path(&path, base_dir),
+                fit: None,
+                background_color: None,
+            },
+            WorkspaceConfigFile::Table(table) => WorkspaceConfig {
+                path: expand_path(&table.path, base_dir),
+                fit: table.fit,
+                background_color: table.background_color,
+            },
+        }
+    }
+}
+
+/// Options which can be set globally on the command line
+/// or in the configuration file and per output in the configuration file
+#[derive(Clone, Copy, Default)]
+pub struct Options {
+    pub level_output_min: Option<f32>,
+    pub level_output_max: Option<f32>,
+    pub level_input_min: Option<f32>,
+    pub level_input_max: Option<f32>,
+    pub pixelformat: Option<PixelFormat>,
+    pub gpu: Option<bool>,
+    pub fit: Option<FitMode>,
+    pub background_color: Option<Color>,
+    pub slideshow_interval: Option<u64>,
+    pub slideshow_order: Option<SlideshowOrder>,
+    pub transition: Option<TransitionMode>,
+    pub transition_duration: Option<u64>,
+    pub transition_easing: Option<TransitionEasing>,
+    pub animation_max_frames: Option<u64>,
+    pub animation_max_memory: Option<u64>,
+    pub random_stable: Option<bool>,
+    pub random_cache_size: Option<u64>,
+}
+
+impl Options {
+    pub fn has_levels(&self) -> bool {
+        self.level_output_min.is_some()
+            || self.level_output_max.is_some()
+            || self.level_input_min.is_some()
+            || self.level_input_max.is_some()
+    }
+
+    pub fn levels(
+        &self,
+        option_name: impl Fn(&str) -> String,
+    ) -> anyhow::Result<Option<Levels>> {
+        levels(
+            self.level_input_min.unwrap_or(0.0),
+            self.level_input_max.unwrap_or(1.0),
+            self.level_output_min.unwrap_or(0.0),
+            self.level_output_max.unwrap_or(1.0),
+            option_name,
+        )
+    }
+
+    // Options set here take precedence over the ones set in other
+    fn or(self, other: Options) -> Options {
+        Options {
+            level_output_min: self.level_output_min.or(other.level_output_min),
+            level_output_max: self.level_output_max.or(other.level_output_max),
+            level_input_min: self.level_input_min.or(other.level_input_min),
+            level_input_max: self.level_input_max.or(other.level_input_max),
+            pixelformat: self.pixelformat.or(other.pixelformat),
+            gpu: self.gpu.or(other.gpu),
+            fit: self.fit.or(other.fit),
+            background_color: self.background_color
+                .or(other.background_color),
+            slideshow_interval: self.slideshow_interval
+                .or(other.slideshow_interval),
+            slideshow_order: self.slideshow_order.or(other.slideshow_order),
+            transition: self.transition.or(other.transition),
+            transition_duration: self.transition_duration
+                .or(other.transition_duration),
+            transition_easing: self.transition_easing
+                .or(other.transition_easing),
+            animation_max_frames: self.animation_max_frames
+                .or(other.animation_max_frames),
+            animation_max_memory: self.animation_max_memory
+                .or(other.animation_max_memory),
+            random_stable: self.random_stable.or(other.random_stable),
+            random_cache_size: self.random_cache_size
+                .or(other.random_cache_size),
+        }
+    }
+
+    fn check_limits(
+        &self,
+        option_name: impl Fn(&str) -> String,
+    ) -> anyhow::Result<()> {
+        if self.slideshow_interval == Some(0) {
+            bail!("{} must be at least 1 second",
+                option_name("slideshow-interval"));
+        }
+        if self.transition_duration == Some(0) {
+            bail!("{} must be at least 1 millisecond",
+                option_name("transition-duration"));
+        }
+        if self.animation_max_frames == Some(0) {
+            bail!("{} must be at least 1", option_name("animation-max-frames"));
+        }
+        if self.animation_max_memory
+            .is_some_and(|mib| !(1..=MAX_ANIMATION_MEMORY).contains(&mib))
+        {
+            bail!("{} must be between 1 and {MAX_ANIMATION_MEMORY} MiB",
+                option_name("animation-max-memory"));
+        }
+        if self.random_cache_size == Some(0) {
+            bail!("{} must be at least 1", option_name("random-cache-size"));
+        }
+        Ok(())
+    }
+}
+
+/// Options resolved for a single output
+#[derive(Clone, Copy)]
+pub struct OutputOptions {
+    pub color_transform: Option<ColorTransform>,
+    pub pixelformat: PixelFormat,
+    pub gpu: bool,
+    pub fit: FitMode,
+    pub background_color: Color,
+    pub slideshow_interval: Duration,
+    pub slideshow_order: SlideshowOrder,
+    pub transition: TransitionMode,
+    pub transition_duration: Duration,
+    pub transition_easing: TransitionEasing,
+    pub animation_max_frames: usize,
+    /// In bytes
+    pub animation_max_memory: usize,
+    pub random_stable: bool,
+    pub random_cache_size: usize,
+}
+
+#[derive(Clone)]
+struct WorkspaceConfig {
+    path: PathBuf,
+    fit: Option<FitMode>,
+    background_color: Option<Color>,
+}
+
+struct OutputConfig {
+    key: String,
+    options: Options,
+    workspaces: BTreeMap<String, WorkspaceConfig>,
+}
+
+pub struct Config {
+    path: Option<PathBuf>,
+    cli_options: Options,
+    cli_on_change: Option<String>,
+    cli_schedule: ScheduleOptions,
+    pub wallpaper_dir: Option<PathBuf>,
+    pub on_change: Option<Rc<ChangeHook>>,
+    pub schedule: Schedule,
+    options: Options,
+    workspaces: BTreeMap<String, WorkspaceConfig>,
+    outputs: Vec<OutputConfig>,
+}
+
+impl Config {
+    pub fn load(
+        path: Option<PathBuf>,
+        cli_options: Options,
+        cli_on_change: Option<String>,
+        cli_schedule: ScheduleOptions,
+    ) -> anyhow::Result<Config> {
+        let Some(path) = path else {
+            return Ok(Config {
+                path: None,
+                cli_options,
+                on_change: cli_on_change.clone()
+                    .map(|command| Rc::new(ChangeHook::new(command))),
+                cli_on_change,
+                schedule: Schedule::new(cli_schedule)?,
+                cli_schedule,
+                wallpaper_dir: None,
+                options: Options::default(),
+                workspaces: BTreeMap::new(),
+                outputs: Vec::new(),
+            })
+        };
+        debug!("Loading configuration file {path:?}");
+        let text = fs::read_to_string(&path).with_context(||
+            format!("Failed to read configuration file {path:?}"))?;
+        let file: ConfigFile = toml::from_str(&text).with_context(||
+            format!("Failed to parse configuration file {path:?}"))?;
+        let base_dir = path.parent().unwrap_or(Path::new("/"));
+        let schedule = Schedule::new(cli_schedule.or(ScheduleOptions {
+            latitude: file.latitude,
+            longitude: file.longitude,
+            day_start: file.day_start,
+            dusk_start: file.dusk_start,
+            night_start: file.night_start,
+        }))?;
+        let options = Options {
+            level_output_min: file.level_output_min,
+            level_output_max: file.level_output_max,
+            level_input_min: file.level_input_min,
+            level_input_max: file.level_input_max,
+            pixelformat: file.pixelformat,
+            gpu: file.gpu,
+            fit: file.fit,
+            background_color: file.background_color,
+            slideshow_interval: file.slideshow_interval,
+            slideshow_order: file.slideshow_order,
+            transition: file.transition,
+            transition_duration: file.transition_duration,
+            transition_easing: file.transition_easing,
+            animation_max_frames: file.animation_max_frames,
+            animation_max_memory: file.animation_max_memory,
+            random_stable: file.random_stable,
+            random_cache_size: file.random_cache_size,
+        };
+        options.levels(|key| format!("Configuration key {key}"))?;
+        options.check_limits(|key|
+            format!("Configuration key {key}"))?;
+        let workspaces = file.workspaces.into_iter()
+            .map(|(workspace, config)| (workspace, config.resolve(base_dir)))
+            .collect();
+        let mut outputs = Vec::new();
+        for (key, output) in file.outputs {
+            let output_options = Options {
+                level_output_min: output.level_output_min,
+                level_output_max: output.level_output_max,
+                level_input_min: output.level_input_min,
+                level_input_max: output.level_input_max,
+                pixelformat: output.pixelformat,
+                gpu: output.gpu,
+                fit: output.fit,
+                background_color: output.background_color,
+                slideshow_interval: output.slideshow_interval,
+                slideshow_order: output.slideshow_order,
+                transition: output.transition,
+                transition_duration: output.transition_duration,
+                transition_easing: output.transition_easing,
+                animation_max_frames: output.animation_max_frames,
+                animation_max_memory: output.animation_max_memory,
+                random_stable: output.random_stable,
+                random_cache_size: output.random_cache_size,
+            };
+            output_options.check_limits(|option|
+                format!("Configuration key outputs.{key:?}.{option}")
+            )?;
+            output_options.levels(|option|
+                format!("Configuration key outputs.{key:?}.{option}")
+            )?;
+            // Check the levels this output will actually use
+            output_options.or(cli_options).or(options).levels(|option|
+                format!("Configuration key outputs.{key:?}.{option} \
+                    (combined with the global levels)")
+            )?;
+            let workspaces = output.workspaces.into_iter()
+                .map(|(workspace, config)| {
+                    (workspace, config.resolve(base_dir))
+                })
+                .collect();
+            outputs.push(OutputConfig {
+                key,
+                options: output_options,
+                workspaces,
+            });
+        }
+        Ok(Config {
+            wallpaper_dir: file.wallpaper_dir
+                .map(|dir| expand_path(&dir, base_dir)),
+            on_change: cli_on_change.clone().or(file.on_change)
+                .map(|command| Rc::new(ChangeHook::new(command))),
+            path: Some(path),
+            cli_options,
+            cli_on_change,
+            schedule,
+            cli_schedule,
+            options,
+            workspaces,
+            outputs,
+        })
+    }
+
+    pub fn reload(&self) -> anyhow::Result<Config> {
+        Config::load(
+            self.path.clone(),
+            self.cli_options,
+            self.cli_on_change.clone(),
+            self.cli_schedule,
+        )
+    }
+
+    pub fn any_gpu(&self) -> bool {
+        self.cli_options.or(self.options).gpu == Some(true)
+            || self.outputs.iter()
+                .any(|output| output.options.gpu == Some(true))
+    }
+
+    fn output(
+        &self,
+        output_name: &str,
+        make_model_serial: &str,
+    ) -> Option<&OutputConfig> {
+        // The most specific match wins,
+        // on ties matching the output name over the make-model-serial
+        self.outputs.iter()
+            .filter_map(|output| {
+                let by_name = match_specificity(&output.key, output_name)
+                    .map(|specificity| (specificity, true));
+                let by_mms = match_specificity(&output.key, make_model_serial)
+                    .filter(|_| !make_model_serial.is_empty())
+                    .map(|specificity| (specificity, false));
+                Some((by_name.max(by_mms)?, output))
+            })
+            .max_by_key(|(key, _)| *key)
+            .map(|(_, output)| output)
+    }
+
+    pub fn output_options(
+        &self,
+        output_name: &str,
+        make_model_serial: &str,
+    ) -> OutputOptions {
+        let output_options = self.output(output_name, make_model_serial)
+            .map(|output| output.options)
+            .unwrap_or_default();
+        let options = output_options.or(self.cli_options).or(self.options);
+        let color_transform = match options.levels(|option|
+            format!("Option {option} for output {output_name}")
+        ) {
+            Ok(levels) => levels.map(ColorTransform::from_levels),
+            Err(e) => {
+                error!("{e:#}, ignoring levels");
+                None
+            }
+        };
+        OutputOptions {
+            color_transform,
+            pixelformat: options.pixelformat.unwrap_or(PixelFormat::Auto),
+            gpu: options.gpu.unwrap_or(false),
+            fit: options.fit.unwrap_or(FitMode::Fill),
+            background_color: options.background_color.unwrap_or_default(),
+            slideshow_interval: Duration::from_secs(
+                options.slideshow_interval.unwrap_or(600)
+            ),
+            slideshow_order: options.slideshow_order
+                .unwrap_or(SlideshowOrder::Sorted),
+            transition: options.transition.unwrap_or(TransitionMode::None),
+            transition_duration: Duration::from_millis(
+                options.transition_duration.unwrap_or(300)
+            ),
+            transition_easing: options.transition_easing
+                .unwrap_or(TransitionEasing::Linear),
+            animation_max_frames: options.animation_max_frames
+                .unwrap_or(300) as usize,
+            animation_max_memory: (options.animation_max_memory
+                .unwrap_or(256) as usize) << 20,
+            random_stable: options.random_stable.unwrap_or(false),
+            random_cache_size: options.random_cache_size.unwrap_or(4) as usize,
+        }
+    }
+
+    /// Wallpaper files explicitly mapped to workspaces for an output,
+    /// these take precedence over the ones in the wallpaper directory
+    pub fn wallpaper_files(
+        &self,
+        output_name: &str,
+        make_model_serial: &str,
+    ) -> Vec<WallpaperFile> {
+        let mut workspaces = self.workspaces.clone();
+        if let Some(output) = self.output(output_name, make_model_serial) {
+            workspaces.extend(output.workspaces.clone());
+        }
+        let mut ret = Vec::new();
+        for (workspace, config) in workspaces {
+            let files = if config.path.is_dir() {
+                slideshow_wallpaper_files(&config.path, &workspace)
+                    .with_context(|| format!("Failed to read slideshow \
+                        directory {:?}", config.path))
+            } else {
+                wallpaper_file(config.path, workspace).map(|file| vec![file])
+            };
+            match files {
+                Ok(files) => ret.extend(files.into_iter()
+                    .map(|mut wallpaper_file| {
+                        wallpaper_file.fit = config.fit;
+                        wallpaper_file.background_color =
+                            config.background_color;
+                        wallpaper_file
+                    })
+                ),
+                Err(e) => error!("Configured wallpaper: {e:#}"),
+            }
+        }
+        ret
+    }
+}
+
+pub fn default_config_path() -> Option<PathBuf> {
+    let config_home = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME")
+            .map(|home| Path::new(&home).join(".config"))
+        )?;
+    let path = config_home.join(env!("CARGO_PKG_NAME")).join("config.toml");
+    path.exists().then_some(path)
+}
+
+fn expand_path(path: &str, base_dir: &Path) -> PathBuf {
+    if let Some(rest) = path.strip_prefix("~/") {
+        if let Some(home) = env::var_os("HOME") {
+            return Path::new(&home).join(rest)
+        }
+    }
+    base_dir.join(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+
+    fn load(name: &str, text: &str, cli_options: Options)
+        -> anyhow::Result<Config>
+    {
+        let path = env::temp_dir().join(format!(
+            "multibg-wayland-test-{}-{name}.toml", process::id()));
+        fs::write(&path, text).unwrap();
+        let config = Config::load(
+            Some(path.clone()),
+            cli_options,
+            None,
+            ScheduleOptions::default(),
+        );
+        fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    fn options_precedence() {
+        let config = load("precedence", r#"
+            fit = "center"
+            slideshow-interval = 100
+            transition-duration = 50
+
+            [outputs.DP-1]
+            fit = "tile"
+        "#, Options {
+            fit: Some(FitMode::Stretch),
+            slideshow_interval: Some(200),
+            ..Options::default()
+        }).unwrap();
+        // The output over the command line over the global options
+        let options = config.output_options("DP-1", "");
+        assert_eq!(options.fit, FitMode::Tile);
+        assert_eq!(options.slideshow_interval, Duration::from_secs(200));
+        assert_eq!(options.transition_duration, Duration::from_millis(50));
+        let options = config.output_options("DP-2", "");
+        assert_eq!(options.fit, FitMode::Stretch);
+        // The defaults without any of them
+        let config = load("defaults", "", Options::default()).unwrap();
+        let options = config.output_options("DP-1", "");
+        assert_eq!(options.fit, FitMode::Fill);
+        assert_eq!(options.slideshow_interval, Duration::from_secs(600));
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let error = load("unknown", r#"wallpaper-dri = "~/a""#,
+            Options::default()).err().unwrap();
+        assert!(format!("{error:#}").contains("unknown field `wallpaper-dri`"),
+            "{error:#}");
+        let error = load("unknown-output", r#"
+            [outputs.DP-1]
+            levle-output-max = 0.5
+        "#, Options::default()).err().unwrap();
+        assert!(format!("{error:#}")
+            .contains("unknown field `levle-output-max`"), "{error:#}");
+        assert!(load("unknown-workspace", r#"
+            [workspaces]
+            1 = { path = "a.jpg", fitt = "center" }
+        "#, Options::default()).is_err());
+    }
+
+    #[test]
+    fn errors_name_the_key() {
+        let error = load("limits", r#"
+            [outputs.DP-1]
+            slideshow-interval = 0
+        "#, Options::default()).err().unwrap();
+        assert!(format!("{error:#}")
+            .contains(r#"outputs."DP-1".slideshow-interval"#), "{error:#}");
+    }
+
+    #[test]
+    fn most_specific_output_section() {
+        let config = load("outputs", r#"
+            [outputs."*"]
+            [outputs."DP-*"]
+            [outputs.DP-1]
+            [outputs."Dell Inc. U2720Q"]
+        "#, Options::default()).unwrap();
+        let key = |output_name, make_model_serial| config
+            .output(output_name, make_model_serial)
+            .map(|output| output.key.as_str());
+        assert_eq!(key("DP-1", ""), Some("DP-1"));
+        assert_eq!(key("DP-2", ""), Some("DP-*"));
+        assert_eq!(key("HDMI-A-1", ""), Some("*"));
+        assert_eq!(key("HDMI-A-1", "Dell Inc. U2720Q ABC"),
+            Some("Dell Inc. U2720Q"));
+        // The exact output name over the make-model-serial
+        assert_eq!(key("DP-1", "Dell Inc. U2720Q ABC"), Some("DP-1"));
+        let config = load("no-outputs", "", Options::default()).unwrap();
+        assert!(config.output("DP-1", "").is_none());
+    }
+}
@@ -0,0 +1,321 @@
+use std::{
+    env,
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    net::Shutdown,
+    os::{
+        fd::{AsFd, BorrowedFd},
+        unix::net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        mpsc::{Receiver, Sender, channel},
+    },
+    thread,
+};
+
+use anyhow::{anyhow, bail, Context};
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use smithay_client_toolkit::reexports::client::{Connection, QueueHandle};
+
+use crate::{
+    State,
+    cli::MsgArgs,
+    events::Subscribers,
+    poll::Waker,
+    wayland::{
+        clear_overrides, reload_all_wallpapers, set_override, set_paused,
+        state_json, toggle_paused,
+    },
+};
+
+// Requests are single lines of JSON, such as:
+//
+// {"command": "reload"}
+// {"command": "state"}
+// {"command": "set", "output": "HDMI-A-1", "workspace": "1", "path": "/a.jpg"}
+// {"command": "set", "output": "HDMI-A-1", "path": "/b.jpg", "persist": true}
+// {"command": "clear", "output": "HDMI-A-1"}
+// {"command": "pause"}
+// {"command": "resume"}
+// {"command": "toggle-pause"}
+// {"command": "subscribe"}
+//
+// Each is answered by a single line of JSON, either
+// {"success": true, "result": ...} or {"success": false, "error": "..."}
+// After subscribe the connection streams events as single lines of JSON
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "command", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Request {
+    /// Reload the configuration, all wallpapers and outputs
+    Reload,
+    /// Query the outputs and their wallpapers
+    State,
+    /// Show an image on a workspace, or on all workspaces of the output
+    Set {
+        output: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        workspace: Option<String>,
+        path: PathBuf,
+        /// Save the override to be restored after restarts
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        persist: bool,
+    },
+    /// Clear the overrides set with the set command, including saved ones
+    Clear {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        output: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        workspace: Option<String>,
+    },
+    /// Stop following workspace switches
+    Pause,
+    /// Continue following workspace switches
+    Resume,
+    /// Pause if following workspace switches, resume if paused
+    TogglePause,
+    /// Stream events on the connection instead of answering requests
+    Subscribe,
+}
+
+/// Request received from a client, to be answered on the main thread
+pub struct Command {
+    request: Request,
+    reply_tx: Sender<anyhow::Result<Value>>,
+}
+
+pub fn socket_path() -> anyhow::Result<PathBuf> {
+    runtime_path("sock")
+}
+
+/// Path of a file in $XDG_RUNTIME_DIR unique to the Wayland display
+pub fn runtime_path(extension: &str) -> anyhow::Result<PathBuf> {
+    let runtime_dir = env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .context("Environment variable XDG_RUNTIME_DIR is not set")?;
+    // WAYLAND_DISPLAY can be a name or an absolute path of a socket
+    let display = env::var_os("WAYLAND_DISPLAY")
+        .filter(|display| !display.is_empty())
+        .unwrap_or_else(|| "wayland-0".into());
+    let display = Path::new(&display).file_name()
+        .context("Invalid WAYLAND_DISPLAY")?
+        .to_string_lossy()
+        .into_owned();
+    Ok(Path::new(&runtime_dir)
+        .join(format!("{}-{}.{}", env!("CARGO_PKG_NAME"), display, extension)))
+}
+
+pub struct ControlSocket {
+    listener: UnixListener,
+    path: PathBuf,
+    tx: Sender<Command>,
+    waker: Arc<Waker>,
+    subscribers: Arc<Subscribers>,
+}
+
+impl ControlSocket {
+    pub fn bind(
+        tx: Sender<Command>,
+        waker: Arc<Waker>,
+        subscribers: Arc<Subscribers>,
+    ) -> anyhow::Result<ControlSocket> {
+        let path = socket_path()?;
+        if UnixStream::connect(&path).is_ok() {
+            bail!("An other instance is already listening on {path:?}");
+        }
+        // Remove the socket left behind by an instance which crashed
+        match fs::remove_file(&path) {
+            Ok(()) => debug!("Removed stale control socket {path:?}"),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e).with_context(||
+                format!("Failed to remove stale control socket {path:?}")),
+        }
+        let listener = UnixListener::bind(&path).with_context(||
+            format!("Failed to bind control socket {path:?}"))?;
+        listener.set_nonblocking(true)
+            .context("Failed to set control socket non-blocking")?;
+        debug!("Listening on control socket {path:?}");
+        Ok(ControlSocket { listener, path, tx, waker, subscribers })
+    }
+
+    /// Accept the pending connections, each served by its own thread
+    /// which passes the requests to the main thread
+    pub fn accept(&self) {
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    error!("Failed to accept control socket connection: {e}");
+                    return
+                }
+            };
+            debug!("New control socket connection");
+            let tx = self.tx.clone();
+            let waker = Arc::clone(&self.waker);
+            let subscribers = Arc::clone(&self.subscribers);
+            let result = thread::Builder::new()
+                .name("control".to_string())
+                .spawn(move || {
+                    if let Err(e) = serve_client(stream, tx, waker, subscribers)
+                    {
+                        debug!("Control socket connection closed: {e}");
+                    }
+                });
+            if let Err(e) = result {
+                error!("Failed to spawn control socket thread: {e}");
+            }
+        }
+    }
+}
+
+impl AsFd for ControlSocket {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.listener.as_fd()
+    }
+}
+
+impl Drop for ControlSocket {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            error!("Failed to remove control socket {:?}: {e}", self.path);
+        }
+    }
+}
+
+fn serve_client(
+    stream: UnixStream,
+    tx: Sender<Command>,
+    waker: Arc<Waker>,
+    subscribers: Arc<Subscribers>,
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue
+        }
+        let result = match serde_json::from_str::<Request>(&line) {
+            Ok(Request::Subscribe) => {
+                debug!("Control socket client subscribed to events");
+                let event_rx = subscribers.subscribe();
+                let reply = json!({ "success": true, "result": null });
+                writeln!(writer, "{reply}")?;
+                // Until the client or the daemon goes away
+                for event in event_rx {
+                    writeln!(writer, "{event}")?;
+                }
+                return Ok(())
+            },
+            Ok(request) => {
+                debug!("Control socket request: {request:?}");
+                let (reply_tx, reply_rx) = channel();
+                if tx.send(Command { request, reply_tx }).is_err() {
+                    return Ok(())
+                }
+                waker.wake();
+                reply_rx.recv()
+                    .unwrap_or_else(|_| Err(anyhow!("Daemon is exiting")))
+            },
+            Err(e) => Err(anyhow!(e).context("Invalid request")),
+        };
+        let reply = match result {
+            Ok(result) => json!({ "success": true, "result": result }),
+            Err(e) => json!({ "success": false, "error": format!("{e:#}") }),
+        };
+        writeln!(writer, "{reply}")?;
+    }
+    Ok(())
+}
+
+pub fn handle_commands(
+    state: &mut State,
+    conn: &Connection,
+    qh: &QueueHandle<State>,
+    rx: &Receiver<Command>,
+) {
+    while let Ok(Command { request, reply_tx }) = rx.try_recv() {
+        let result = match request {
+            Request::Reload => {
+                reload_all_wallpapers(state, conn, qh);
+                Ok(Value::Null)
+            },
+            Request::State => Ok(state_json(state)),
+            Request::Set { output, workspace, path, persist } => {
+                set_override(state, conn, qh, &output, workspace, path, persist)
+                    .map(|()| Value::Null)
+            },
+            Request::Clear { output, workspace } => {
+                clear_overrides(state, output.as_deref(), workspace.as_deref())
+                    .map(|cleared| json!({ "cleared": cleared }))
+            },
+            Request::Pause => {
+                set_paused(state, true);
+                Ok(Value::Null)
+            },
+            Request::Resume => {
+                set_paused(state, false);
+                Ok(Value::Null)
+            },
+            Request::TogglePause => {
+                toggle_paused(state);
+                Ok(json!({ "paused": state.paused }))
+            },
+            Request::Subscribe => unreachable!("handled by serve_client"),
+        };
+        if let Err(e) = &result {
+            debug!("Control socket request failed: {e:#}");
+        }
+        // The client might have disconnected already
+        let _ = reply_tx.send(result);
+    }
+}
+
+/// Client side of the msg subcommand, returns whether the request succeeded
+pub fn msg(args: MsgArgs) -> anyhow::Result<bool> {
+    let request = args.request.into_request()?;
+    let path = socket_path()?;
+    let mut stream = UnixStream::connect(&path).with_context(||
+        format!("Failed to connect to the running instance at {path:?}"))?;
+    let mut line = serde_json::to_string(&request).unwrap();
+    line.push('\n');
+    stream.write_all(line.as_bytes())
+        .context("Failed to send request")?;
+    stream.shutdown(Shutdown::Write)
+        .context("Failed to send request")?;
+    let mut reply = String::new();
+    let mut reader = BufReader::new(stream);
+    reader.read_line(&mut reply)
+        .context("Failed to receive reply")?;
+    if reply.is_empty() {
+        bail!("Running instance closed the connection without reply");
+    }
+    let reply: Value = serde_json::from_str(&reply)
+        .context("Failed to parse reply")?;
+    let success = reply["success"].as_bool().unwrap_or(false);
+    if success && matches!(request, Request::Subscribe) {
+        // Print the events until the running instance exits
+        for event in reader.lines() {
+            let event = event.context("Failed to receive event")?;
+            println!("{event}");
+        }
+        return Ok(true)
+    }
+    if args.raw {
+        println!("{reply}");
+    } else if success {
+        if !reply["result"].is_null() {
+            println!("{}", serde_json::to_string_pretty(&reply["result"])
+                .unwrap());
+        }
+    } else {
+        eprintln!("Error: {}", reply["error"].as_str()
+            .unwrap_or("unknown error"));
+    }
+    Ok(success)
+}
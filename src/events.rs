@@ -0,0 +1,89 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        Mutex,
+        mpsc::{Receiver, SyncSender, TrySendError, sync_channel},
+    },
+};
+
+use log::warn;
+use serde::Serialize;
+
+// Events queued for a subscriber which does not keep up with reading them
+const QUEUED_EVENTS: usize = 64;
+
+// Events are streamed to subscribed clients of the control socket
+// as single lines of JSON, such as:
+//
+// {"event": "wallpaper-switched", "output": "HDMI-A-1", "workspace": "1", ...}
+// {"event": "output-removed", "output": "HDMI-A-1"}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    /// The wallpaper shown on an output has changed
+    WallpaperSwitched {
+        output: &'a str,
+        workspace: &'a str,
+        workspace_number: i32,
+        path: &'a PathBuf,
+        canonical_path: &'a PathBuf,
+    },
+    /// The wallpapers of an output were loaded or reloaded
+    WallpapersLoaded {
+        output: &'a str,
+        loaded: usize,
+        reused: usize,
+        errors: usize,
+    },
+    /// A wallpaper of an output failed to load
+    WallpaperFailed {
+        output: &'a str,
+        path: &'a PathBuf,
+        error: &'a str,
+    },
+    OutputAdded {
+        output: &'a str,
+        width: i32,
+        height: i32,
+    },
+    OutputRemoved {
+        output: &'a str,
+    },
+    /// The configuration, all wallpapers and outputs were reloaded
+    Reloaded,
+}
+
+/// Clients subscribed to the events, shared with the control socket threads
+#[derive(Default)]
+pub struct Subscribers {
+    senders: Mutex<Vec<SyncSender<String>>>,
+}
+
+impl Subscribers {
+    /// The events end when the subscriber is dropped
+    pub fn subscribe(&self) -> Receiver<String> {
+        let (tx, rx) = sync_channel(QUEUED_EVENTS);
+        self.senders.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Send the event to the subscribers, dropping the disconnected ones
+    /// and the ones too far behind, without ever blocking
+    pub fn emit(&self, event: Event) {
+        let mut senders = self.senders.lock().unwrap();
+        if senders.is_empty() {
+            return
+        }
+        let line = serde_json::to_string(&event).unwrap();
+        senders.retain(|tx| match tx.try_send(line.clone()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                warn!("Dropping an event subscriber which has fallen \
+                    {QUEUED_EVENTS} events behind");
+                false
+            },
+            Err(TrySendError::Disconnected(_)) => false,
+        });
+    }
+}
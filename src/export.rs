@@ -0,0 +1,171 @@
+use std::{
+    collections::HashMap,
+    env,
+    fs,
+    io,
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, Sender},
+    thread,
+};
+
+use anyhow::Context;
+use fast_image_resize::Resizer;
+use image::ExtendedColorType;
+use log::{debug, error};
+use smithay_client_toolkit::reexports::client::protocol::wl_shm;
+
+use crate::image::{load_wallpaper, Processing};
+
+/// Keeps $XDG_RUNTIME_DIR/multibg-wayland/<output> a symlink to the wallpaper
+/// shown on the output, and optionally <output>.png the processed wallpaper
+pub struct Exporter {
+    dir: PathBuf,
+    png_tx: Option<Sender<PngJob>>,
+}
+
+struct PngJob {
+    output_name: String,
+    canon_path: PathBuf,
+    width: u32,
+    height: u32,
+    processing: Processing,
+}
+
+impl Exporter {
+    pub fn new(png: bool) -> anyhow::Result<Exporter> {
+        let runtime_dir = env::var_os("XDG_RUNTIME_DIR")
+            .filter(|dir| !dir.is_empty())
+            .context("Environment variable XDG_RUNTIME_DIR is not set")?;
+        let dir = Path::new(&runtime_dir).join(env!("CARGO_PKG_NAME"));
+        fs::create_dir_all(&dir).with_context(||
+            format!("Failed to create directory {dir:?}"))?;
+        let png_tx = if png {
+            let (tx, rx) = channel();
+            let png_dir = dir.clone();
+            thread::Builder::new()
+                .name("export".to_string())
+                .spawn(move || export_pngs(&png_dir, rx))
+                .context("Failed to spawn PNG export thread")?;
+            Some(tx)
+        } else {
+            None
+        };
+        debug!("Exporting the shown wallpapers to {dir:?}");
+        Ok(Exporter { dir, png_tx })
+    }
+
+    pub fn export(
+        &self,
+        output_name: &str,
+        canon_path: &Path,
+        width: i32,
+        height: i32,
+        processing: Processing,
+    ) {
+        let link_path = self.dir.join(output_name);
+        if let Err(e) = replace_symlink(canon_path, &link_path) {
+            error!("Failed to update symlink {link_path:?}: {e}");
+        }
+        if let Some(png_tx) = &self.png_tx {
+            let _ = png_tx.send(PngJob {
+                output_name: output_name.to_string(),
+                canon_path: canon_path.to_path_buf(),
+                width: width as u32,
+                height: height as u32,
+                processing,
+            });
+        }
+    }
+
+    /// Remove the exported files of an output which was removed
+    pub fn remove(&self, output_name: &str) {
+        let link_path = self.dir.join(output_name);
+        let png_path = self.dir.join(format!("{output_name}.png"));
+        for path in [link_path, png_path] {
+            match fs::remove_file(&path) {
+                Ok(()) => (),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+                Err(e) => error!("Failed to remove {path:?}: {e}"),
+            }
+        }
+    }
+}
+
+// Replace atomically by renaming a new symlink over the old one
+fn replace_symlink(target: &Path, link_path: &Path) -> io::Result<()> {
+    let mut tmp_name = link_path.file_name().unwrap().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = link_path.with_file_name(tmp_name);
+    match fs::remove_file(&tmp_path) {
+        Ok(()) => (),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e),
+    }
+    symlink(target, &tmp_path)?;
+    fs::rename(&tmp_path, link_path)
+}
+
+fn export_pngs(dir: &Path, rx: Receiver<PngJob>) {
+    let mut resizer = Resizer::new();
+    let mut exported = HashMap::<String, PngJob>::new();
+    while let Ok(job) = rx.recv() {
+        // Only the latest wallpaper of each output is worth exporting
+        let mut jobs = vec![job];
+        while let Ok(job) = rx.try_recv() {
+            jobs.retain(|queued| queued.output_name != job.output_name);
+            jobs.push(job);
+        }
+        for job in jobs {
+            if exported.get(&job.output_name).is_some_and(|previous|
+                previous.canon_path == job.canon_path
+                    && previous.width == job.width
+                    && previous.height == job.height
+                    && previous.processing == job.processing
+            ) {
+                continue
+            }
+            let png_path = dir.join(format!("{}.png", job.output_name));
+            match export_png(&job, &png_path, &mut resizer) {
+                Ok(()) => {
+                    debug!("Exported wallpaper of output {} to {png_path:?}",
+                        job.output_name);
+                    exported.insert(job.output_name.clone(), job);
+                },
+                Err(e) => error!("Failed to export wallpaper of output {} \
+                    to {png_path:?}: {e:#}", job.output_name),
+            }
+        }
+    }
+}
+
+fn export_png(
+    job: &PngJob,
+    png_path: &Path,
+    resizer: &mut Resizer,
+) -> anyhow::Result<()> {
+    let stride = job.width as usize * 3;
+    let mut buffer = vec![0u8; stride * job.height as usize];
+    // Bgr888 is RGB in byte order
+    load_wallpaper(
+        &job.canon_path,
+        &mut buffer,
+        job.width,
+        job.height,
+        stride,
+        wl_shm::Format::Bgr888,
+        job.processing,
+        resizer,
+    )?;
+    let tmp_path = png_path.with_extension("png.tmp");
+    image::save_buffer_with_format(
+        &tmp_path,
+        &buffer,
+        job.width,
+        job.height,
+        ExtendedColorType::Rgb8,
+        image::ImageFormat::Png,
+    ).context("Failed to write PNG")?;
+    fs::rename(&tmp_path, png_path).context("Failed to replace PNG")?;
+    Ok(())
+}
@@ -0,0 +1,138 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+    thread,
+    time::Duration,
+};
+
+use log::{debug, error, warn};
+
+const REAP_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Shell command run after the wallpaper shown on an output has changed
+pub struct ChangeHook {
+    tx: Option<Sender<HookJob>>,
+}
+
+struct HookJob {
+    output_name: String,
+    workspace_name: String,
+    workspace_number: i32,
+    path: PathBuf,
+    canon_path: PathBuf,
+}
+
+impl ChangeHook {
+    pub fn new(command: String) -> ChangeHook {
+        let (tx, rx) = channel();
+        let result = thread::Builder::new()
+            .name("hook".to_string())
+            .spawn(move || run_hooks(&command, rx));
+        match result {
+            Ok(_) => ChangeHook { tx: Some(tx) },
+            Err(e) => {
+                error!("Failed to spawn on-change hook thread: {e}");
+                ChangeHook { tx: None }
+            },
+        }
+    }
+
+    /// Pass the change to the hook thread without waiting for the command
+    pub fn run(
+        &self,
+        output_name: &str,
+        workspace_name: &str,
+        workspace_number: i32,
+        path: &Path,
+        canon_path: &Path,
+    ) {
+        let Some(tx) = &self.tx else { return };
+        let _ = tx.send(HookJob {
+            output_name: output_name.to_string(),
+            workspace_name: workspace_name.to_string(),
+            workspace_number,
+            path: path.to_path_buf(),
+            canon_path: canon_path.to_path_buf(),
+        });
+    }
+}
+
+// Run the command for each change and reap it, at most one at a time
+// for each output. Changes while the command is still running for their
+// output are coalesced into a single run with the latest one after it.
+fn run_hooks(command: &str, rx: Receiver<HookJob>) {
+    let mut running = HashMap::<String, Child>::new();
+    let mut queued = HashMap::<String, HookJob>::new();
+    let mut disconnected = false;
+    loop {
+        // Only poll for the exits while any command is running
+        let received = if running.is_empty() {
+            if disconnected {
+                return
+            }
+            rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else if disconnected {
+            thread::sleep(REAP_INTERVAL);
+            Err(RecvTimeoutError::Timeout)
+        } else {
+            rx.recv_timeout(REAP_INTERVAL)
+        };
+        match received {
+            Ok(job) if running.contains_key(&job.output_name) => {
+                debug!("On-change hook for output {} is still running, \
+                    queueing the latest change", job.output_name);
+                queued.insert(job.output_name.clone(), job);
+            },
+            Ok(job) => spawn_hook(command, job, &mut running),
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => disconnected = true,
+        }
+        let mut exited = Vec::new();
+        for (output_name, child) in running.iter_mut() {
+            match child.try_wait() {
+                Ok(None) => continue,
+                Ok(Some(status)) if status.success() => (),
+                Ok(Some(status)) => {
+                    warn!("On-change hook {command:?} failed: {status}");
+                },
+                Err(e) => {
+                    error!("Failed to wait for on-change hook {command:?}: \
+                        {e}");
+                },
+            }
+            exited.push(output_name.clone());
+        }
+        for output_name in exited {
+            running.remove(&output_name);
+            if let Some(job) = queued.remove(&output_name) {
+                spawn_hook(command, job, &mut running);
+            }
+        }
+    }
+}
+
+fn spawn_hook(
+    command: &str,
+    job: HookJob,
+    running: &mut HashMap<String, Child>,
+) {
+    debug!("Running on-change hook for output {}", job.output_name);
+    let result = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("MULTIBG_OUTPUT", &job.output_name)
+        .env("MULTIBG_WORKSPACE", &job.workspace_name)
+        .env("MULTIBG_WORKSPACE_NUMBER", job.workspace_number.to_string())
+        .env("MULTIBG_WALLPAPER", &job.path)
+        .env("MULTIBG_WALLPAPER_CANONICAL", &job.canon_path)
+        .stdin(Stdio::null())
+        .spawn();
+    match result {
+        Ok(child) => {
+            running.insert(job.output_name, child);
+        },
+        Err(e) => error!("Failed to run on-change hook {command:?}: {e}"),
+    }
+}
@@ -1,22 +1,33 @@
 #![allow(clippy::too_many_arguments)]
 
 use std::{
-    fs::read_dir,
+    fmt,
+    fs::{read_dir, File},
+    io::BufReader,
     path::{Path, PathBuf},
-    time::UNIX_EPOCH,
+    str::FromStr,
+    time::{Duration, UNIX_EPOCH},
 };
 
-use anyhow::{bail, Context};
+use anyhow::{anyhow, bail, Context};
 use fast_image_resize::{
     FilterType, PixelType, Resizer, ResizeAlg, ResizeOptions,
     images::Image,
 };
 use image::{
-    ColorType, DynamicImage, ImageBuffer, ImageDecoder, ImageReader, Pixel,
+    AnimationDecoder, ColorType, DynamicImage, Frame, Frames, ImageBuffer,
+    ImageDecoder, ImageFormat, ImageReader, Pixel, RgbImage,
+    codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder},
 };
 use log::{debug, error, warn};
+use serde::Deserialize;
 use smithay_client_toolkit::reexports::client::protocol::wl_shm;
 
+use crate::{
+    pattern::{NamePattern, match_specificity},
+    schedule::TimeOfDay,
+};
+
 #[derive(Clone, Copy, PartialEq)]
 pub struct Levels {
     pub input_min: f32,
@@ -178,12 +189,239 @@ impl ColorTransform {
     }
 }
 
+/// How images are fit to the size of the output
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FitMode {
+    /// Scale to fill the output, cropping the image
+    Fill,
+    /// Scale to fit within the output, with bars of the background color
+    Fit,
+    /// Center without scaling
+    Center,
+    /// Repeat without scaling from the top left corner
+    Tile,
+    /// Scale to the size of the output ignoring the aspect ratio
+    Stretch,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl FromStr for Color {
+    type Err = anyhow::Error;
+
+    // Parse #rgb or #rrggbb
+    fn from_str(s: &str) -> anyhow::Result<Color> {
+        let err = || anyhow!("Invalid color '{s}', expected #rgb or #rrggbb");
+        let hex = s.strip_prefix('#').ok_or_else(err)?;
+        if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(err())
+        }
+        let channel = |i: usize, len: usize| -> u8 {
+            let value = u8::from_str_radix(&hex[i * len..][..len], 16)
+                .unwrap();
+            if len == 1 { value * 17 } else { value }
+        };
+        match hex.len() {
+            3 | 6 => {
+                let len = hex.len() / 3;
+                Ok(Color {
+                    r: channel(0, len),
+                    g: channel(1, len),
+                    b: channel(2, len),
+                })
+            },
+            _ => Err(err()),
+        }
+    }
+}
+
+impl TryFrom<String> for Color {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> anyhow::Result<Color> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum GradientDirection {
+    /// Top to bottom
+    Vertical,
+    /// Left to right
+    Horizontal,
+    /// Top left to bottom right
+    Diagonal,
+}
+
+/// Wallpaper described by a .color text file instead of an image, such as:
+/// `#1e1e2e` or `linear #000 #334` or `linear diagonal #000 #334`
+#[derive(Debug)]
+enum ColorWallpaper {
+    Solid(Color),
+    Linear {
+        direction: GradientDirection,
+        from: Color,
+        to: Color,
+    },
+}
+
+impl FromStr for ColorWallpaper {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<ColorWallpaper> {
+        let mut words = s.split_whitespace();
+        let ret = match words.next() {
+            Some("linear") => {
+                let mut word = words.next();
+                let direction = match word {
+                    Some("vertical") => Some(GradientDirection::Vertical),
+                    Some("horizontal") => Some(GradientDirection::Horizontal),
+                    Some("diagonal") => Some(GradientDirection::Diagonal),
+                    _ => None,
+                };
+                if direction.is_some() {
+                    word = words.next();
+                }
+                let from = word.context("Missing gradient start color")?
+                    .parse()?;
+                let to = words.next().context("Missing gradient end color")?
+                    .parse()?;
+                ColorWallpaper::Linear {
+                    direction: direction
+                        .unwrap_or(GradientDirection::Vertical),
+                    from,
+                    to,
+                }
+            },
+            Some(color) => ColorWallpaper::Solid(color.parse()?),
+            None => bail!("Empty color description"),
+        };
+        if let Some(word) = words.next() {
+            bail!("Unexpected '{word}' after the color description")
+        }
+        Ok(ret)
+    }
+}
+
+impl ColorWallpaper {
+    fn load(path: &Path) -> anyhow::Result<ColorWallpaper> {
+        let text = std::fs::read_to_string(path)
+            .context("Failed to read color file")?;
+        let color_wallpaper = text.parse()
+            .context("Failed to parse color file, expected eg. \
+                '#1e1e2e' or 'linear [vertical|horizontal|diagonal] \
+                #000 #334'")?;
+        debug!("Color wallpaper {color_wallpaper:?}");
+        Ok(color_wallpaper)
+    }
+
+    fn draw(
+        &self,
+        dst: &mut [u8],
+        width: usize,
+        height: usize,
+        stride: usize,
+        format: wl_shm::Format,
+        color_transform: Option<ColorTransform>,
+    ) {
+        let transform = |color: Color| match color_transform {
+            Some(ct) => Color {
+                r: ct.apply(color.r),
+                g: ct.apply(color.g),
+                b: ct.apply(color.b),
+            },
+            None => color,
+        };
+        let pixel_size = match format {
+            wl_shm::Format::Bgr888 => 3,
+            wl_shm::Format::Xrgb8888 => 4,
+            _ => unreachable!(),
+        };
+        let put = |pixel: &mut [u8], color: Color| match format {
+            wl_shm::Format::Bgr888 => {
+                pixel.copy_from_slice(&[color.r, color.g, color.b]);
+            },
+            _ => pixel.copy_from_slice(&[color.b, color.g, color.r, 0xff]),
+        };
+        let (direction, from, to) = match *self {
+            ColorWallpaper::Solid(color) => {
+                (GradientDirection::Vertical, color, color)
+            },
+            ColorWallpaper::Linear { direction, from, to } => {
+                (direction, from, to)
+            },
+        };
+        let (from, to) = (transform(from), transform(to));
+        let steps = match direction {
+            GradientDirection::Vertical => height - 1,
+            GradientDirection::Horizontal => width - 1,
+            GradientDirection::Diagonal => width + height - 2,
+        }.max(1);
+        for (y, row) in dst.chunks_exact_mut(stride).take(height).enumerate() {
+            let pixels = row[..width * pixel_size]
+                .chunks_exact_mut(pixel_size);
+            for (x, pixel) in pixels.enumerate() {
+                let step = match direction {
+                    GradientDirection::Vertical => y,
+                    GradientDirection::Horizontal => x,
+                    GradientDirection::Diagonal => x + y,
+                };
+                put(pixel, interpolate(from, to, step, steps));
+            }
+        }
+    }
+}
+
+fn interpolate(from: Color, to: Color, step: usize, steps: usize) -> Color {
+    let channel = |from: u8, to: u8| -> u8 {
+        let from = from as i64;
+        let to = to as i64;
+        let steps = steps as i64;
+        let value = from * steps + (to - from) * step as i64;
+        ((value + steps / 2).div_euclid(steps)) as u8
+    };
+    Color {
+        r: channel(from.r, to.r),
+        g: channel(from.g, to.g),
+        b: channel(from.b, to.b),
+    }
+}
+
+/// Everything besides the image file and the output size
+/// which determines the pixels of a loaded wallpaper
+#[derive(Clone, Copy, PartialEq)]
+pub struct Processing {
+    pub color_transform: Option<ColorTransform>,
+    pub fit: FitMode,
+    pub background: Color,
+}
+
 pub struct WallpaperFile {
     pub path: PathBuf,
     pub workspace: String,
     pub workspace_number: i32,
+    /// Shown only at this time of day
+    pub variant: Option<TimeOfDay>,
+    /// Shown only while the workspace has windows
+    pub occupied: bool,
     pub canon_path: PathBuf,
     pub canon_modified: u128,
+    pub pattern: Option<NamePattern>,
+    pub fit: Option<FitMode>,
+    pub background_color: Option<Color>,
 }
 
 pub fn output_wallpaper_files(
@@ -201,40 +439,142 @@ pub fn output_wallpaper_files(
         };
         let path = dir_entry.path();
         if path.is_dir() {
-            warn!("Skipping nested directory {path:?}");
+            let workspace = path.file_name().unwrap()
+                .to_string_lossy().into_owned();
+            match slideshow_wallpaper_files(&path, &workspace) {
+                Ok(files) => ret.extend(files),
+                Err(e) => error!("Failed to read slideshow directory \
+                    {path:?}: {e:#}"),
+            }
             continue
         }
         let workspace = path.file_stem().unwrap()
             .to_string_lossy().into_owned();
-        let workspace_number: i32 = workspace.parse().unwrap_or_default();
-        let canon_path = match path.canonicalize() {
-            Ok(canon_path) => canon_path,
-            Err(e) => {
-                error!("Failed to resolve absolute path for {path:?}: {e}");
-                continue
-            }
+        match wallpaper_file(path, workspace) {
+            Ok(wallpaper_file) => ret.push(wallpaper_file),
+            Err(e) => error!("{e:#}"),
+        }
+    }
+    Ok(ret)
+}
+
+pub struct OutputDirMatch {
+    pub name: String,
+    pub by_make_model_serial: bool,
+    pub candidates: usize,
+}
+
+/// Find the directory in wallpaper_dir which matches the output
+/// most specifically by a glob or by the first words of its
+/// make-model-serial string, for when no name matches exactly
+pub fn find_output_dir(
+    wallpaper_dir: &Path,
+    output_name: &str,
+    make_model_serial: &str,
+) -> Option<OutputDirMatch> {
+    let dir = match read_dir(wallpaper_dir) {
+        Ok(dir) => dir,
+        Err(e) => {
+            error!("Failed to read wallpaper directory: {e}");
+            return None
+        }
+    };
+    let mut candidates = Vec::new();
+    for dir_entry in dir.flatten() {
+        if !dir_entry.path().is_dir() {
+            continue
+        }
+        let Ok(name) = dir_entry.file_name().into_string() else {
+            continue
         };
-        let canon_metadata = match canon_path.metadata() {
-            Ok(canon_metadata) => canon_metadata,
-            Err(e) => {
-                error!("Failed to get file metadata for {canon_path:?}: {e}");
-                continue
-            }
+        let by_name = match_specificity(&name, output_name);
+        let by_mms = if make_model_serial.is_empty() {
+            None
+        } else {
+            match_specificity(&name, make_model_serial)
+        };
+        let best = match (by_name, by_mms) {
+            (Some(a), Some(b)) if b > a => Some((b, true)),
+            (Some(a), _) => Some((a, false)),
+            (None, Some(b)) => Some((b, true)),
+            (None, None) => None,
         };
-        let canon_modified = canon_metadata.modified().unwrap()
-            .duration_since(UNIX_EPOCH).unwrap()
-            .as_nanos();
-        ret.push(WallpaperFile {
-            path,
-            workspace,
-            workspace_number,
-            canon_path,
-            canon_modified,
-        });
+        if let Some((specificity, by_make_model_serial)) = best {
+            candidates.push((specificity, name, by_make_model_serial));
+        }
+    }
+    // Most specific first, ties broken by name to be deterministic
+    candidates.sort_unstable_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    let count = candidates.len();
+    let (_, name, by_make_model_serial) = candidates.into_iter().next()?;
+    Some(OutputDirMatch { name, by_make_model_serial, candidates: count })
+}
+
+/// Images of a workspace slideshow directory sorted by file name
+pub fn slideshow_wallpaper_files(
+    dir: &Path,
+    workspace: &str,
+) -> anyhow::Result<Vec<WallpaperFile>> {
+    let mut paths = Vec::new();
+    for dir_entry_result in read_dir(dir).context("Failed to read directory")? {
+        let path = dir_entry_result
+            .context("Failed to read directory entries")?
+            .path();
+        if path.is_dir() {
+            warn!("Skipping directory {path:?} nested in slideshow");
+            continue
+        }
+        paths.push(path);
+    }
+    paths.sort_unstable();
+    let mut ret = Vec::new();
+    for path in paths {
+        match wallpaper_file(path, workspace.to_string()) {
+            Ok(wallpaper_file) => ret.push(wallpaper_file),
+            Err(e) => error!("{e:#}"),
+        }
+    }
+    if ret.is_empty() {
+        warn!("Slideshow directory {dir:?} has no images");
     }
     Ok(ret)
 }
 
+pub fn wallpaper_file(
+    path: PathBuf,
+    workspace: String,
+) -> anyhow::Result<WallpaperFile> {
+    let (workspace, occupied) = match workspace.strip_suffix(".occupied") {
+        Some(name) if !name.is_empty() => (name.to_string(), true),
+        _ => (workspace, false),
+    };
+    let (workspace, variant) = match TimeOfDay::split_suffix(&workspace) {
+        (name, Some(variant)) => (name.to_string(), Some(variant)),
+        (_, None) => (workspace, None),
+    };
+    let workspace_number: i32 = workspace.parse().unwrap_or_default();
+    let pattern = NamePattern::parse(&workspace).transpose()?;
+    let canon_path = path.canonicalize().with_context(||
+        format!("Failed to resolve absolute path for {path:?}"))?;
+    let canon_metadata = canon_path.metadata().with_context(||
+        format!("Failed to get file metadata for {canon_path:?}"))?;
+    let canon_modified = canon_metadata.modified().unwrap()
+        .duration_since(UNIX_EPOCH).unwrap()
+        .as_nanos();
+    Ok(WallpaperFile {
+        path,
+        workspace,
+        workspace_number,
+        variant,
+        occupied,
+        canon_path,
+        canon_modified,
+        pattern,
+        fit: None,
+        background_color: None,
+    })
+}
+
 pub fn load_wallpaper(
     path: &Path,
     buffer: &mut [u8],
@@ -242,7 +582,7 @@ pub fn load_wallpaper(
     surface_height: u32,
     surface_stride: usize,
     surface_format: wl_shm::Format,
-    color_transform: Option<ColorTransform>,
+    processing: Processing,
     resizer: &mut Resizer,
 ) -> anyhow::Result<()> {
     let surface_size = surface_stride * surface_height as usize;
@@ -250,6 +590,33 @@ pub fn load_wallpaper(
         bail!("Provided buffer size {} smaller than wallpaper image size {}",
             buffer.len(), surface_size);
     };
+    if path.extension().is_some_and(|ext| ext == "color") {
+        let color_wallpaper = ColorWallpaper::load(path)?;
+        color_wallpaper.draw(
+            dst,
+            surface_width as usize,
+            surface_height as usize,
+            surface_stride,
+            surface_format,
+            processing.color_transform,
+        );
+        return Ok(())
+    }
+    let decoder = open_image(path)?.into_decoder()
+        .context("Failed to initialize image decoder")?;
+    draw_decoder(
+        decoder,
+        dst,
+        surface_width,
+        surface_height,
+        surface_stride,
+        surface_format,
+        processing,
+        resizer,
+    )
+}
+
+fn open_image(path: &Path) -> anyhow::Result<ImageReader<BufReader<File>>> {
     let reader = ImageReader::open(path)
         .context("Failed to open image file")?
         .with_guessed_format()
@@ -262,8 +629,79 @@ pub fn load_wallpaper(
         bail!("Application was compiled with support \
             for image file format {file_format:?} disabled")
     }
-    let mut decoder = reader.into_decoder()
-        .context("Failed to initialize image decoder")?;
+    Ok(reader)
+}
+
+/// Decoder of a still image, or the frames of an animated GIF, APNG
+/// or WebP image to be decoded one at a time
+pub enum ImageFrames {
+    Still(Box<dyn ImageDecoder>),
+    Animated(Frames<'static>),
+}
+
+/// Open an image file which may be animated, not a .color file
+pub fn open_image_frames(path: &Path) -> anyhow::Result<ImageFrames> {
+    let reader = open_image(path)?;
+    let format = reader.format();
+    if !matches!(format, Some(
+        ImageFormat::Gif | ImageFormat::Png | ImageFormat::WebP
+    )) {
+        return Ok(ImageFrames::Still(Box::new(reader.into_decoder()
+            .context("Failed to initialize image decoder")?)))
+    }
+    let reader = reader.into_inner();
+    Ok(match format {
+        Some(ImageFormat::Gif) => ImageFrames::Animated(
+            GifDecoder::new(reader)
+                .context("Failed to initialize GIF decoder")?
+                .into_frames()
+        ),
+        Some(ImageFormat::Png) => {
+            let decoder = PngDecoder::new(reader)
+                .context("Failed to initialize PNG decoder")?;
+            if decoder.is_apng().context("Failed to read PNG")? {
+                ImageFrames::Animated(decoder.apng()
+                    .context("Failed to initialize APNG decoder")?
+                    .into_frames())
+            } else {
+                ImageFrames::Still(Box::new(decoder))
+            }
+        },
+        Some(ImageFormat::WebP) => {
+            let decoder = WebPDecoder::new(reader)
+                .context("Failed to initialize WebP decoder")?;
+            if decoder.has_animation() {
+                ImageFrames::Animated(decoder.into_frames())
+            } else {
+                ImageFrames::Still(Box::new(decoder))
+            }
+        },
+        _ => unreachable!(),
+    })
+}
+
+/// How long to show an animation frame
+pub fn frame_delay(frame: &Frame) -> Duration {
+    let delay = Duration::from(frame.delay());
+    // Same as web browsers, which slow down the too fast frames
+    if delay < Duration::from_millis(20) {
+        Duration::from_millis(100)
+    } else {
+        delay
+    }
+}
+
+/// Decode and write a still image to the buffer
+pub fn draw_decoder(
+    mut decoder: impl ImageDecoder,
+    dst: &mut [u8],
+    surface_width: u32,
+    surface_height: u32,
+    surface_stride: usize,
+    surface_format: wl_shm::Format,
+    processing: Processing,
+    resizer: &mut Resizer,
+) -> anyhow::Result<()> {
     let (image_width, image_height) = decoder.dimensions();
     let image_size = decoder.total_bytes();
     let image_color_type = decoder.color_type();
@@ -271,9 +709,6 @@ pub fn load_wallpaper(
         bail!("Image has invalid dimensions {image_width}x{image_height}")
     };
     debug!("Image {image_width}x{image_height} {image_color_type:?}");
-    if image_color_type.has_alpha() {
-        warn!("Image has alpha channel which will be ignored");
-    }
     if let Ok(Some(_)) = decoder.icc_profile() {
         debug!("Image has an embedded ICC color profile \
             but ICC color profile handling is not yet implemented");
@@ -284,57 +719,228 @@ pub fn load_wallpaper(
     if !needs_resize
         && image_color_type == ColorType::Rgb8
         && surface_format == wl_shm::Format::Bgr888
-        && color_transform.is_none()
+        && processing.color_transform.is_none()
         && surface_row_len == surface_stride
     {
         debug!("Decoding image directly to destination buffer");
-        decoder.read_image(dst).context("Failed to decode image")?;
+        let surface_size = surface_stride * surface_height as usize;
+        decoder.read_image(&mut dst[..surface_size])
+            .context("Failed to decode image")?;
         return Ok(());
     }
     let image = DynamicImage::from_decoder(decoder)
         .context("Failed to decode image")?;
-    let mut image = image.into_rgb8();
-    if let Some(ct) = color_transform {
+    draw_image(
+        image,
+        dst,
+        surface_width,
+        surface_height,
+        surface_stride,
+        surface_format,
+        processing,
+        resizer,
+    )
+}
+
+/// Process and write a decoded image or animation frame to the buffer
+pub fn draw_image(
+    image: DynamicImage,
+    dst: &mut [u8],
+    surface_width: u32,
+    surface_height: u32,
+    surface_stride: usize,
+    surface_format: wl_shm::Format,
+    processing: Processing,
+    resizer: &mut Resizer,
+) -> anyhow::Result<()> {
+    let mut image = if image.color().has_alpha() {
+        debug!("Blending the alpha channel of the image onto \
+            the background color {}", processing.background);
+        blend_onto(image.into_rgba8(), processing.background)
+    } else {
+        image.into_rgb8()
+    };
+    let mut background = processing.background;
+    if let Some(ct) = processing.color_transform {
         for (_, _, pixel) in image.enumerate_pixels_mut() {
             pixel.apply(|subpixel| ct.apply(subpixel))
         }
+        background = Color {
+            r: ct.apply(background.r),
+            g: ct.apply(background.g),
+            b: ct.apply(background.b),
+        };
     }
-    if needs_resize {
-        debug!("Resizing image from {}x{} to {}x{}",
-            image_width, image_height,
-            surface_width, surface_height
-        );
-        let src_image = Image::from_vec_u8(
-            image_width,
-            image_height,
-            image.into_raw(),
-            PixelType::U8x3,
-        ).unwrap();
-        let mut dst_image = Image::new(
-            surface_width,
-            surface_height,
-            PixelType::U8x3,
-        );
-        resizer.resize(
-            &src_image,
-            &mut dst_image,
-            &ResizeOptions::new()
-                .fit_into_destination(None)
-                .resize_alg(ResizeAlg::Convolution(FilterType::Lanczos3))
-        ).context("Failed to resize image")?;
-        image = ImageBuffer::from_raw(
+    let image = fit_image(
+        image,
+        surface_width,
+        surface_height,
+        processing.fit,
+        background,
+        resizer,
+    )?;
+    let surface_size = surface_stride * surface_height as usize;
+    write_to_surface(&image, &mut dst[..surface_size], surface_stride,
+        surface_format);
+    Ok(())
+}
+
+fn fit_image(
+    image: RgbImage,
+    surface_width: u32,
+    surface_height: u32,
+    fit: FitMode,
+    background: Color,
+    resizer: &mut Resizer,
+) -> anyhow::Result<RgbImage> {
+    let (image_width, image_height) = image.dimensions();
+    if image_width == surface_width && image_height == surface_height {
+        return Ok(image)
+    }
+    match fit {
+        FitMode::Fill | FitMode::Stretch => resize(
+            image,
             surface_width,
             surface_height,
-            dst_image.into_vec()
-        ).unwrap();
+            fit == FitMode::Fill,
+            resizer,
+        ),
+        FitMode::Fit => {
+            let scale = f64::min(
+                surface_width as f64 / image_width as f64,
+                surface_height as f64 / image_height as f64,
+            );
+            let width = ((image_width as f64 * scale).round() as u32)
+                .clamp(1, surface_width);
+            let height = ((image_height as f64 * scale).round() as u32)
+                .clamp(1, surface_height);
+            let image = resize(image, width, height, false, resizer)?;
+            Ok(center(&image, surface_width, surface_height, background))
+        },
+        FitMode::Center => {
+            debug!("Centering image {}x{} on {}x{}",
+                image_width, image_height, surface_width, surface_height);
+            Ok(center(&image, surface_width, surface_height, background))
+        },
+        FitMode::Tile => {
+            debug!("Tiling image {}x{} on {}x{}",
+                image_width, image_height, surface_width, surface_height);
+            Ok(tile(&image, surface_width, surface_height))
+        },
+    }
+}
+
+fn resize(
+    image: RgbImage,
+    width: u32,
+    height: u32,
+    crop: bool,
+    resizer: &mut Resizer,
+) -> anyhow::Result<RgbImage> {
+    let (image_width, image_height) = image.dimensions();
+    if image_width == width && image_height == height {
+        return Ok(image)
     }
+    debug!("Resizing image from {}x{} to {}x{}",
+        image_width, image_height, width, height);
+    let src_image = Image::from_vec_u8(
+        image_width,
+        image_height,
+        image.into_raw(),
+        PixelType::U8x3,
+    ).unwrap();
+    let mut dst_image = Image::new(width, height, PixelType::U8x3);
+    let mut options = ResizeOptions::new()
+        .resize_alg(ResizeAlg::Convolution(FilterType::Lanczos3));
+    if crop {
+        options = options.fit_into_destination(None);
+    }
+    resizer.resize(&src_image, &mut dst_image, &options)
+        .context("Failed to resize image")?;
+    Ok(ImageBuffer::from_raw(width, height, dst_image.into_vec()).unwrap())
+}
+
+// Place the image in the center of a canvas filled with the background,
+// cropping it evenly on the sides where it is larger than the canvas
+fn center(
+    image: &RgbImage,
+    width: u32,
+    height: u32,
+    background: Color,
+) -> RgbImage {
+    let mut canvas = RgbImage::from_pixel(
+        width,
+        height,
+        image::Rgb([background.r, background.g, background.b]),
+    );
+    let (image_width, image_height) = image.dimensions();
+    let copy_width = image_width.min(width) as usize;
+    let copy_height = image_height.min(height);
+    let src_x = (image_width.saturating_sub(width) / 2) as usize;
+    let src_y = image_height.saturating_sub(height) / 2;
+    let dst_x = (width.saturating_sub(image_width) / 2) as usize;
+    let dst_y = height.saturating_sub(image_height) / 2;
+    let src_stride = image_width as usize * 3;
+    let dst_stride = width as usize * 3;
+    let src = image.as_raw();
+    let dst: &mut [u8] = &mut canvas;
+    for row in 0..copy_height {
+        let src_start = (src_y + row) as usize * src_stride + src_x * 3;
+        let dst_start = (dst_y + row) as usize * dst_stride + dst_x * 3;
+        dst[dst_start..][..copy_width * 3]
+            .copy_from_slice(&src[src_start..][..copy_width * 3]);
+    }
+    canvas
+}
+
+fn tile(image: &RgbImage, width: u32, height: u32) -> RgbImage {
+    let (image_width, image_height) = image.dimensions();
+    let src_stride = image_width as usize * 3;
+    let dst_stride = width as usize * 3;
+    let src = image.as_raw();
+    let mut canvas = RgbImage::new(width, height);
+    let dst: &mut [u8] = &mut canvas;
+    for row in 0..height as usize {
+        let src_row = &src[(row % image_height as usize) * src_stride..]
+            [..src_stride];
+        let dst_row = &mut dst[row * dst_stride..][..dst_stride];
+        for dst_chunk in dst_row.chunks_mut(src_stride) {
+            dst_chunk.copy_from_slice(&src_row[..dst_chunk.len()]);
+        }
+    }
+    canvas
+}
+
+fn blend_onto(image: image::RgbaImage, background: Color) -> RgbImage {
+    let (width, height) = image.dimensions();
+    let bg = [background.r, background.g, background.b];
+    let mut ret = RgbImage::new(width, height);
+    for (dst, src) in ret.pixels_mut().zip(image.pixels()) {
+        let alpha = src[3] as u32;
+        for i in 0..3 {
+            let blended = src[i] as u32 * alpha
+                + bg[i] as u32 * (255 - alpha);
+            dst[i] = ((blended + 127) / 255) as u8;
+        }
+    }
+    ret
+}
+
+fn write_to_surface(
+    image: &RgbImage,
+    dst: &mut [u8],
+    surface_stride: usize,
+    surface_format: wl_shm::Format,
+) {
+    let surface_row_len = image.width() as usize * 3;
+    let surface_height = image.height();
     match surface_format {
         wl_shm::Format::Bgr888 => {
             if surface_row_len == surface_stride {
-                dst.copy_from_slice(&image);
+                dst.copy_from_slice(image);
             } else {
                 copy_pad_stride(
-                    &image,
+                    image,
                     dst,
                     surface_row_len,
                     surface_stride,
@@ -343,11 +949,10 @@ pub fn load_wallpaper(
             }
         },
         wl_shm::Format::Xrgb8888 => {
-            swizzle_bgra_from_rgb(&image, dst);
+            swizzle_bgra_from_rgb(image, dst);
         },
         _ => unreachable!(),
     }
-    Ok(())
 }
 
 fn copy_pad_stride(
@@ -400,3 +1005,162 @@ unsafe fn bgra_from_rgb(src: &[u8], dst: &mut [u8], pixel_count: usize) {
 fn mulhi(left: u16, right: u16) -> u16 {
     (((left as u32) * (right as u32)) >> 16) as u16
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: Color = Color { r: 0, g: 0, b: 0 };
+    const WHITE: Color = Color { r: 0xff, g: 0xff, b: 0xff };
+
+    #[test]
+    fn parse_colors() {
+        assert_eq!("#1e1e2e".parse::<Color>().unwrap(),
+            Color { r: 0x1e, g: 0x1e, b: 0x2e });
+        assert_eq!("#F0a".parse::<Color>().unwrap(),
+            Color { r: 0xff, g: 0x00, b: 0xaa });
+        for invalid in ["", "#", "1e1e2e", "#12", "#1234", "#12345g", "#+12"] {
+            assert!(invalid.parse::<Color>().is_err(), "{invalid:?}");
+        }
+        assert_eq!(Color { r: 1, g: 0xab, b: 0xff }.to_string(), "#01abff");
+    }
+
+    #[test]
+    fn parse_color_wallpapers() {
+        let parse = |s: &str| s.parse::<ColorWallpaper>();
+        assert!(matches!(parse("#fff\n").unwrap(),
+            ColorWallpaper::Solid(WHITE)));
+        assert!(matches!(parse("linear #000 #fff").unwrap(),
+            ColorWallpaper::Linear {
+                direction: GradientDirection::Vertical,
+                from: BLACK,
+                to: WHITE,
+            }));
+        for (word, direction) in [
+            ("vertical", GradientDirection::Vertical),
+            ("horizontal", GradientDirection::Horizontal),
+            ("diagonal", GradientDirection::Diagonal),
+        ] {
+            let wallpaper = parse(&format!("linear {word} #fff #000"))
+                .unwrap();
+            let ColorWallpaper::Linear { direction: parsed, from, to } =
+                wallpaper
+            else {
+                panic!("{wallpaper:?}");
+            };
+            assert_eq!((parsed, from, to), (direction, WHITE, BLACK));
+        }
+        for invalid in [
+            "",
+            "white",
+            "linear",
+            "linear #000",
+            "linear diagonal #000",
+            "linear sideways #000 #fff",
+            "linear #000 #fff #333",
+            "#000 #fff",
+        ] {
+            assert!(parse(invalid).is_err(), "{invalid:?}");
+        }
+    }
+
+    #[test]
+    fn interpolate_gradient() {
+        let from = Color { r: 0, g: 100, b: 255 };
+        let to = Color { r: 255, g: 100, b: 0 };
+        assert_eq!(interpolate(from, to, 0, 4), from);
+        assert_eq!(interpolate(from, to, 4, 4), to);
+        assert_eq!(interpolate(from, to, 2, 4),
+            Color { r: 128, g: 100, b: 128 });
+        assert_eq!(interpolate(from, to, 1, 4),
+            Color { r: 64, g: 100, b: 191 });
+        // One step for each value in between
+        for step in 0..=255 {
+            let color = interpolate(BLACK, WHITE, step, 255);
+            assert_eq!(color, Color { r: step as u8, g: step as u8,
+                b: step as u8 });
+        }
+    }
+
+    // Each pixel tells where it came from
+    fn numbered(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| {
+            image::Rgb([x as u8, y as u8, 1])
+        })
+    }
+
+    const RED: image::Rgb<u8> = image::Rgb([0xff, 0, 0]);
+    const BACKGROUND: Color = Color { r: 0xff, g: 0, b: 0 };
+
+    #[test]
+    fn center_larger_image() {
+        // Cropped evenly on both sides
+        let canvas = center(&numbered(7, 6), 3, 2, BACKGROUND);
+        assert_eq!(canvas.dimensions(), (3, 2));
+        assert_eq!(canvas.get_pixel(0, 0), &image::Rgb([2, 2, 1]));
+        assert_eq!(canvas.get_pixel(2, 1), &image::Rgb([4, 3, 1]));
+    }
+
+    #[test]
+    fn center_smaller_image() {
+        let canvas = center(&numbered(2, 1), 5, 3, BACKGROUND);
+        assert_eq!(canvas.dimensions(), (5, 3));
+        for (x, y, pixel) in canvas.enumerate_pixels() {
+            if (1..3).contains(&x) && y == 1 {
+                assert_eq!(pixel, &image::Rgb([x as u8 - 1, 0, 1]));
+            } else {
+                assert_eq!(pixel, &RED, "{x},{y}");
+            }
+        }
+        // Smaller on one side and larger on the other
+        let canvas = center(&numbered(1, 5), 3, 3, BACKGROUND);
+        assert_eq!(canvas.get_pixel(1, 0), &image::Rgb([0, 1, 1]));
+        assert_eq!(canvas.get_pixel(1, 2), &image::Rgb([0, 3, 1]));
+        assert_eq!(canvas.get_pixel(0, 1), &RED);
+    }
+
+    #[test]
+    fn tile_images() {
+        let canvas = tile(&numbered(3, 2), 7, 5);
+        assert_eq!(canvas.dimensions(), (7, 5));
+        for (x, y, pixel) in canvas.enumerate_pixels() {
+            assert_eq!(pixel, &image::Rgb([(x % 3) as u8, (y % 2) as u8, 1]));
+        }
+        // A larger image is cropped from the top left corner
+        let canvas = tile(&numbered(5, 4), 2, 3);
+        for (x, y, pixel) in canvas.enumerate_pixels() {
+            assert_eq!(pixel, &image::Rgb([x as u8, y as u8, 1]));
+        }
+    }
+
+    #[test]
+    fn fit_modes() {
+        let mut resizer = Resizer::new();
+        let mut fit = |image, fit| fit_image(image, 4, 4, fit, BACKGROUND,
+            &mut resizer).unwrap();
+        for mode in [FitMode::Fill, FitMode::Fit, FitMode::Center,
+            FitMode::Tile, FitMode::Stretch]
+        {
+            // Of the same size as the output it is shown as it is
+            assert_eq!(fit(numbered(4, 4), mode), numbered(4, 4));
+            assert_eq!(fit(numbered(8, 2), mode).dimensions(), (4, 4));
+            assert_eq!(fit(numbered(1, 3), mode).dimensions(), (4, 4));
+        }
+        // Bars above and below the wide image scaled to fit
+        let canvas = fit(RgbImage::new(8, 4), FitMode::Fit);
+        for (x, y, pixel) in canvas.enumerate_pixels() {
+            if y == 0 || y == 3 {
+                assert_eq!(pixel, &RED, "{x},{y}");
+            } else {
+                assert_eq!(pixel, &image::Rgb([0, 0, 0]), "{x},{y}");
+            }
+        }
+        // Filled without bars
+        let canvas = fit(RgbImage::new(8, 4), FitMode::Fill);
+        assert!(canvas.pixels().all(|pixel| pixel == &image::Rgb([0, 0, 0])));
+        // Centered without scaling
+        let canvas = fit(numbered(2, 2), FitMode::Center);
+        assert_eq!(canvas.get_pixel(1, 1), &image::Rgb([0, 0, 1]));
+        assert_eq!(canvas.get_pixel(0, 0), &RED);
+    }
+}
@@ -0,0 +1,131 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek},
+    os::unix::fs::{FileExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+    process,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use log::{debug, error, info};
+use rustix::fs::{flock, FlockOperation};
+
+use crate::control::runtime_path;
+
+const REPLACE_TIMEOUT: Duration = Duration::from_secs(10);
+const PID_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Lock held for the lifetime of the process, allowing a single instance
+/// for each Wayland display. Released by the kernel when the process exits.
+/// Without a usable $XDG_RUNTIME_DIR running without the lock is allowed.
+pub struct InstanceLock {
+    _file: File,
+}
+
+impl InstanceLock {
+    /// Take the lock, or with replace ask the running instance to exit
+    /// with SIGTERM and wait for it to release the lock.
+    /// Fails only if an other instance holds the lock, returns None
+    /// if the lock could not be set up.
+    pub fn acquire(replace: bool) -> anyhow::Result<Option<InstanceLock>> {
+        let (file, path) = match open_lock_file() {
+            Ok(opened) => opened,
+            Err(e) => {
+                error!("Failed to set up the instance lock: {e:#}");
+                return Ok(None)
+            },
+        };
+        match try_lock(&file, &path) {
+            Ok(true) => (),
+            Ok(false) => take_over(&file, &path, replace)?,
+            Err(e) => {
+                error!("Failed to set up the instance lock: {e:#}");
+                return Ok(None)
+            },
+        }
+        // Overwritten in place instead of truncated first
+        // to never show an empty file to an other instance
+        let pid = format!("{}\n", process::id());
+        if let Err(e) = file.write_all_at(pid.as_bytes(), 0)
+            .and_then(|()| file.set_len(pid.len() as u64))
+        {
+            error!("Failed to write lock file {path:?}: {e}");
+        }
+        debug!("Acquired instance lock {path:?}");
+        Ok(Some(InstanceLock { _file: file }))
+    }
+}
+
+// Called when an other instance holds the lock, waits for it to exit
+// if asked to replace it
+fn take_over(
+    file: &File,
+    path: &Path,
+    replace: bool,
+) -> anyhow::Result<()> {
+    let pid = read_pid(file, path)?;
+    if !replace {
+        bail!("An other instance is already running with PID {pid} \
+            (holding the lock {path:?}), use --replace to replace it");
+    }
+    info!("Replacing the running instance with PID {pid}");
+    if unsafe { libc::kill(pid, libc::SIGTERM) } < 0 {
+        return Err(io::Error::last_os_error()).with_context(||
+            format!("Failed to send SIGTERM to PID {pid}"))
+    }
+    let deadline = Instant::now() + REPLACE_TIMEOUT;
+    while !try_lock(file, path)? {
+        if Instant::now() >= deadline {
+            bail!("The running instance with PID {pid} did not exit \
+                in {} seconds", REPLACE_TIMEOUT.as_secs());
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    debug!("The replaced instance with PID {pid} exited");
+    Ok(())
+}
+
+fn open_lock_file() -> anyhow::Result<(File, PathBuf)> {
+    let path = runtime_path("lock")?;
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o600)
+        .open(&path)
+        .with_context(|| format!("Failed to open lock file {path:?}"))?;
+    Ok((file, path))
+}
+
+// Returns false if an other process holds the lock
+fn try_lock(file: &File, path: &Path) -> anyhow::Result<bool> {
+    match flock(file, FlockOperation::NonBlockingLockExclusive) {
+        Ok(()) => Ok(true),
+        Err(rustix::io::Errno::WOULDBLOCK) => Ok(false),
+        Err(e) => Err(e).with_context(||
+            format!("Failed to lock lock file {path:?}")),
+    }
+}
+
+// The other instance writes its PID right after taking the lock,
+// so an invalid one is read again until it had the time to finish
+fn read_pid(mut file: &File, path: &Path) -> anyhow::Result<i32> {
+    let deadline = Instant::now() + PID_TIMEOUT;
+    loop {
+        let mut text = String::new();
+        file.rewind()
+            .and_then(|()| file.read_to_string(&mut text))
+            .with_context(|| format!("Failed to read lock file {path:?}"))?;
+        match text.trim().parse() {
+            Ok(pid) => return Ok(pid),
+            Err(_) if Instant::now() < deadline => {
+                thread::sleep(Duration::from_millis(10));
+            },
+            Err(e) => return Err(e).with_context(||
+                format!("Invalid PID in lock file {path:?}")),
+        }
+    }
+}
@@ -1,25 +1,44 @@
 #![deny(unused_must_use)]
 #![allow(clippy::uninlined_format_args)]
 
+mod animation;
 mod cli;
 mod compositors;
+mod config;
+mod control;
+mod events;
+mod export;
 mod gpu;
+mod hook;
 mod image;
+mod instance;
+mod pattern;
+mod persist;
 mod poll;
+mod random;
+mod schedule;
+mod sd_notify;
 mod signal;
+mod slideshow;
+mod transition;
+mod watch;
 mod wayland;
 
 use std::{
     env,
     io,
     os::fd::AsFd,
-    path::{Path, PathBuf},
+    path::PathBuf,
+    process,
+    rc::Rc,
     sync::{
         Arc,
         mpsc::{channel, Receiver},
     },
+    time::Instant,
 };
 
+use anyhow::Context;
 use clap::Parser;
 use log::{debug, error, info, warn};
 use rustix::{
@@ -35,7 +54,7 @@ use smithay_client_toolkit::{
     shm::Shm,
 };
 use smithay_client_toolkit::reexports::client::{
-    Connection, EventQueue,
+    Connection, EventQueue, QueueHandle,
     backend::{ReadEventsGuard, WaylandError},
     globals::registry_queue_init,
     protocol::wl_shm,
@@ -44,13 +63,27 @@ use smithay_client_toolkit::reexports::protocols
     ::wp::viewporter::client::wp_viewporter::WpViewporter;
 
 use crate::{
-    cli::{Cli, PixelFormat},
+    cli::{Cli, Command, PixelFormat},
     compositors::{Compositor, ConnectionTask, WorkspaceVisible},
+    config::{Config, default_config_path},
+    control::{ControlSocket, handle_commands, msg},
+    events::Subscribers,
+    export::Exporter,
     gpu::Gpu,
-    image::{ColorTransform, Levels},
-    poll::{Poll, Waker},
+    image::Levels,
+    instance::InstanceLock,
+    persist::OverrideStore,
+    poll::{Poll, Timer, Waker},
+    sd_notify::SdNotify,
     signal::SignalPipe,
-    wayland::BackgroundLayer,
+    slideshow::Rng,
+    watch::DirWatcher,
+    wayland::{
+        BackgroundLayer, advance_animations, advance_slideshows,
+        advance_time_of_day, load_picked_random_wallpapers,
+        print_state_report, reload_all_wallpapers,
+        reload_changed_wallpapers, toggle_paused, update_animation_timer,
+    },
 };
 
 struct State {
@@ -63,15 +96,29 @@ struct State {
     wallpaper_dir: PathBuf,
     shm_format: Option<wl_shm::Format>,
     background_layers: Vec<BackgroundLayer>,
+    compositor: Compositor,
     compositor_connection_task: ConnectionTask,
-    color_transform: Option<ColorTransform>,
+    config: Config,
     dmabuf_state: DmabufState,
     gpu: Option<Gpu>,
     show_serials: bool,
+    dir_watcher: Option<DirWatcher>,
+    slideshow_timer: Option<Timer>,
+    animation_timer: Option<Timer>,
+    time_of_day_timer: Option<Timer>,
+    rng: Rng,
+    paused: bool,
+    override_store: OverrideStore,
+    subscribers: Arc<Subscribers>,
+    sd_notify: Option<SdNotify>,
+    exporter: Option<Rc<Exporter>>,
 }
 
 impl State {
-    fn shm_format(&mut self) -> wl_shm::Format {
+    fn shm_format(&mut self, pixelformat: PixelFormat) -> wl_shm::Format {
+        if pixelformat == PixelFormat::Baseline {
+            return wl_shm::Format::Xrgb8888
+        }
         *self.shm_format.get_or_insert_with(|| {
             let mut format = wl_shm::Format::Xrgb8888;
             // Consume less gpu memory by using Bgr888 if available,
@@ -86,10 +133,22 @@ impl State {
 }
 
 fn main() -> Result<(), ()> {
-    run().map_err(|e| { error!("{e:#}"); })
+    let cli = Cli::parse();
+    if let Some(Command::Msg(args)) = cli.command {
+        // Exit without the debug print of an Err returned from main
+        match msg(args) {
+            Ok(true) => process::exit(0),
+            Ok(false) => process::exit(1),
+            Err(e) => {
+                eprintln!("Error: {e:#}");
+                process::exit(1)
+            },
+        }
+    }
+    run(cli).map_err(|e| { error!("{e:#}"); })
 }
 
-fn run() -> anyhow::Result<()> {
+fn run(cli: Cli) -> anyhow::Result<()> {
     env_logger::Builder::from_env(
         env_logger::Env::default().default_filter_or(
             #[cfg(debug_assertions)]
@@ -103,7 +162,8 @@ fn run() -> anyhow::Result<()> {
 
     let show_serials = env::var_os("MULTIBG_DEBUG_SHOW_SERIALS").is_some();
 
-    let cli = Cli::parse();
+    // Before starting any threads as it removes its environment variables
+    let sd_notify = SdNotify::from_env();
 
     let compositor = cli.compositor
         .or_else(Compositor::from_env)
@@ -116,8 +176,29 @@ fn run() -> anyhow::Result<()> {
         return Ok(())
     }
 
-    let wallpaper_dir = Path::new(&cli.wallpaper_dir).canonicalize().unwrap();
-    let color_transform = cli.levels()?.map(ColorTransform::from_levels);
+    // Held until exiting, after the control socket is removed
+    let _instance_lock = InstanceLock::acquire(cli.replace)?;
+
+    let config_path = cli.config.as_ref().map(PathBuf::from)
+        .or_else(default_config_path);
+    let config = Config::load(
+        config_path,
+        cli.options()?,
+        cli.on_change.clone(),
+        cli.schedule_options(),
+    )?;
+
+    let wallpaper_dir = cli.wallpaper_dir.as_ref().map(PathBuf::from)
+        .or_else(|| config.wallpaper_dir.clone())
+        .unwrap_or_else(|| PathBuf::from("."));
+    let wallpaper_dir = wallpaper_dir.canonicalize().with_context(||
+        format!("Failed to resolve wallpaper directory {wallpaper_dir:?}"))?;
+
+    let exporter = Exporter::new(cli.export_png)
+        .map_err(|e| error!("Failed to set up exporting the shown \
+            wallpapers: {e:#}"))
+        .ok()
+        .map(Rc::new);
 
     // ********************************
     //     Initialize wayland client
@@ -130,12 +211,6 @@ fn run() -> anyhow::Result<()> {
     let compositor_state = CompositorState::bind(&globals, &qh).unwrap();
     let layer_shell = LayerShell::bind(&globals, &qh).unwrap();
     let shm = Shm::bind(&globals, &qh).unwrap();
-    let shm_format = if cli.pixelformat == Some(PixelFormat::Baseline) {
-        debug!("Using shm format: {:?}", wl_shm::Format::Xrgb8888);
-        Some(wl_shm::Format::Xrgb8888)
-    } else {
-        None
-    };
 
     let registry_state = RegistryState::new(&globals);
 
@@ -144,7 +219,7 @@ fn run() -> anyhow::Result<()> {
 
     let dmabuf_state = DmabufState::new(&globals, &qh);
     let mut gpu = None;
-    if cli.gpu {
+    if config.any_gpu() {
         if let Some(version) = dmabuf_state.version() {
             if version >= 4 {
                 debug!("Using Linux DMA-BUF version {version}");
@@ -166,6 +241,23 @@ fn run() -> anyhow::Result<()> {
         }
     }
 
+    let dir_watcher = DirWatcher::new()
+        .map_err(|e| error!("Failed to set up watching the wallpaper \
+            directory for changes: {e}"))
+        .ok();
+
+    let slideshow_timer = Timer::new()
+        .map_err(|e| error!("Failed to set up the slideshow timer: {e}"))
+        .ok();
+
+    let animation_timer = Timer::new()
+        .map_err(|e| error!("Failed to set up the animation timer: {e}"))
+        .ok();
+
+    let time_of_day_timer = Timer::new()
+        .map_err(|e| error!("Failed to set up the time of day timer: {e}"))
+        .ok();
+
     // Sync tools for sway ipc tasks
     let (tx, rx) = channel();
     let waker = Arc::new(Waker::new().unwrap());
@@ -178,26 +270,41 @@ fn run() -> anyhow::Result<()> {
         layer_shell,
         viewporter,
         wallpaper_dir,
-        shm_format,
+        shm_format: None,
         background_layers: Vec::new(),
+        compositor,
         compositor_connection_task: ConnectionTask::new(
             compositor, tx.clone(), Arc::clone(&waker)
         ),
-        color_transform,
+        config,
         dmabuf_state,
         gpu,
         show_serials,
+        dir_watcher,
+        slideshow_timer,
+        animation_timer,
+        time_of_day_timer,
+        rng: Rng::new(),
+        paused: false,
+        override_store: OverrideStore::load(),
+        subscribers: Arc::new(Subscribers::default()),
+        sd_notify,
+        exporter,
     };
 
     event_queue.roundtrip(&mut state).unwrap();
 
     debug!("Initial wayland roundtrip done. Starting main event loop.");
 
+    if let Some(sd_notify) = state.sd_notify.as_mut() {
+        sd_notify.set_startup_outputs(&state.background_layers);
+    }
+
     // ********************************
     //     Main event loop
     // ********************************
 
-    let mut poll = Poll::with_capacity(3);
+    let mut poll = Poll::with_capacity(10);
     let token_wayland = poll.add_readable(&conn);
     ConnectionTask::spawn_subscribe_event_loop(compositor, tx, waker.clone());
     let token_compositor = poll.add_readable(&waker);
@@ -205,8 +312,51 @@ fn run() -> anyhow::Result<()> {
         .map_err(|e| error!("Failed to set up signal handling: {e}"))
         .ok();
     let token_signal = signal_pipe.as_ref().map(|pipe| poll.add_readable(pipe));
+    let dir_watcher_fd = state.dir_watcher.as_ref().map(DirWatcher::fd);
+    let token_dir_watcher = dir_watcher_fd.as_ref()
+        .map(|fd| poll.add_readable(fd));
+    let (control_tx, control_rx) = channel();
+    let control_waker = Arc::new(Waker::new().unwrap());
+    let control_socket = ControlSocket::bind(
+        control_tx,
+        Arc::clone(&control_waker),
+        Arc::clone(&state.subscribers),
+    )
+        .map_err(|e| error!("Failed to set up the control socket: {e:#}"))
+        .ok();
+    let token_control = control_socket.as_ref()
+        .map(|socket| poll.add_readable(socket));
+    let token_control_waker = poll.add_readable(&*control_waker);
+    let slideshow_timer_fd = state.slideshow_timer.as_ref().map(Timer::fd);
+    let token_slideshow_timer = slideshow_timer_fd.as_ref()
+        .map(|fd| poll.add_readable(fd));
+    let animation_timer_fd = state.animation_timer.as_ref().map(Timer::fd);
+    let token_animation_timer = animation_timer_fd.as_ref()
+        .map(|fd| poll.add_readable(fd));
+    let time_of_day_timer_fd = state.time_of_day_timer.as_ref().map(Timer::fd);
+    let token_time_of_day_timer = time_of_day_timer_fd.as_ref()
+        .map(|fd| poll.add_readable(fd));
+    let watchdog_interval = state.sd_notify.as_ref()
+        .and_then(SdNotify::watchdog_interval);
+    let watchdog_timer = watchdog_interval
+        .and_then(|_| Timer::new()
+            .map_err(|e| error!("Failed to create watchdog timer: {e}"))
+            .ok()
+        );
+    let watchdog_timer_fd = watchdog_timer.as_ref().map(Timer::fd);
+    let token_watchdog_timer = watchdog_timer_fd.as_ref()
+        .map(|fd| poll.add_readable(fd));
+    if let (Some(timer), Some(interval)) = (&watchdog_timer, watchdog_interval)
+    {
+        timer.set_deadline(Some(Instant::now() + interval));
+    }
 
     loop {
+        if let Some(sd_notify) = state.sd_notify.as_mut() {
+            sd_notify.check_ready(&state.background_layers);
+        }
+        load_picked_random_wallpapers(&mut state);
+        update_animation_timer(&state);
         flush_blocking(&conn);
         let read_guard = ensure_prepare_read(&mut state, &mut event_queue);
         poll.poll().expect("Main event loop poll failed");
@@ -219,6 +369,48 @@ fn run() -> anyhow::Result<()> {
             waker.read();
             handle_sway_event(&mut state, &rx);
         }
+        if let Some(token_dir_watcher) = token_dir_watcher {
+            if poll.ready(token_dir_watcher) {
+                handle_dir_watcher_event(&mut state, &conn, &qh);
+            }
+        }
+        if let Some(token_control) = token_control {
+            if poll.ready(token_control) {
+                control_socket.as_ref().unwrap().accept();
+            }
+        }
+        if poll.ready(token_control_waker) {
+            control_waker.read();
+            handle_commands(&mut state, &conn, &qh, &control_rx);
+        }
+        if let Some(token_slideshow_timer) = token_slideshow_timer {
+            if poll.ready(token_slideshow_timer) {
+                state.slideshow_timer.as_ref().unwrap().read();
+                advance_slideshows(&mut state);
+            }
+        }
+        if let Some(token_animation_timer) = token_animation_timer {
+            if poll.ready(token_animation_timer) {
+                state.animation_timer.as_ref().unwrap().read();
+                advance_animations(&mut state);
+            }
+        }
+        if let Some(token_time_of_day_timer) = token_time_of_day_timer {
+            if poll.ready(token_time_of_day_timer) {
+                state.time_of_day_timer.as_ref().unwrap().read();
+                advance_time_of_day(&mut state);
+            }
+        }
+        if let Some(token_watchdog_timer) = token_watchdog_timer {
+            if poll.ready(token_watchdog_timer) {
+                let timer = watchdog_timer.as_ref().unwrap();
+                timer.read();
+                state.sd_notify.as_ref().unwrap().notify("WATCHDOG=1");
+                timer.set_deadline(Some(
+                    Instant::now() + watchdog_interval.unwrap()
+                ));
+            }
+        }
         if let Some(token_signal) = token_signal {
             if poll.ready(token_signal) {
                 match signal_pipe.as_ref().unwrap().read() {
@@ -226,12 +418,23 @@ fn run() -> anyhow::Result<()> {
                     Ok(signal_flags) => {
                         if let Some(signal) = signal_flags.any_termination() {
                             info!("Received signal {signal}, exiting");
+                            if let Some(sd_notify) = &state.sd_notify {
+                                sd_notify.notify("STOPPING=1");
+                            }
                             return Ok(());
-                        } else if signal_flags.has_usr1()
-                            || signal_flags.has_usr2()
-                        {
-                            error!("Received signal USR1 or USR2 is \
-                                reserved for future functionality");
+                        }
+                        if signal_flags.has_usr1() {
+                            info!("Received signal USR1, reloading \
+                                wallpapers and outputs");
+                            reload_all_wallpapers(&mut state, &conn, &qh);
+                        }
+                        if signal_flags.has_usr2() {
+                            info!("Received signal USR2, reporting state");
+                            print_state_report(&state);
+                        }
+                        if signal_flags.has_rtmin() {
+                            info!("Received signal RTMIN, toggling pause");
+                            toggle_paused(&mut state);
                         }
                     },
                 }
@@ -291,6 +494,23 @@ fn handle_wayland_event(
     }
 }
 
+fn handle_dir_watcher_event(
+    state: &mut State,
+    conn: &Connection,
+    qh: &QueueHandle<State>,
+) {
+    let changed_dirs = match state.dir_watcher.as_mut().unwrap().read() {
+        Ok(changed_dirs) => changed_dirs,
+        Err(e) => {
+            error!("Failed to read wallpaper directory changes: {e}");
+            return
+        }
+    };
+    if !changed_dirs.is_empty() {
+        reload_changed_wallpapers(state, conn, qh, &changed_dirs);
+    }
+}
+
 fn handle_sway_event(
     state: &mut State,
     rx: &Receiver<WorkspaceVisible>,
@@ -300,10 +520,21 @@ fn handle_sway_event(
         if let Some(affected_bg_layer) = state.background_layers.iter_mut()
             .find(|bg_layer| bg_layer.output_name == workspace.output)
         {
-            affected_bg_layer.draw_workspace_bg(
-                &workspace.workspace_name,
-                workspace.workspace_number,
-            );
+            // While paused only remember the visible workspace,
+            // but still draw on outputs without any wallpaper yet
+            if state.paused && affected_bg_layer.has_wallpaper() {
+                affected_bg_layer.set_visible_workspace(
+                    &workspace.workspace_name,
+                    workspace.workspace_number,
+                    workspace.occupied,
+                );
+            } else {
+                affected_bg_layer.draw_workspace_bg(
+                    &workspace.workspace_name,
+                    workspace.workspace_number,
+                    workspace.occupied,
+                );
+            }
         } else {
             error!(
                 "Workspace '{}' is on an unknown output '{}', \
@@ -0,0 +1,221 @@
+use anyhow::Context;
+use regex::Regex;
+
+/// Whether the string has any glob special characters: * ? [
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+/// Match a glob pattern against the whole text, supporting:
+/// `*` any characters, `?` one character, `[abc]` `[a-z]` `[!abc]` classes
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    let mut p = 0;
+    let mut t = 0;
+    // Position after the last * in the pattern and the text position
+    // it was tried at, to backtrack to when the rest fails to match
+    let mut backtrack: Option<(usize, usize)> = None;
+    while t < text.len() {
+        // Length of the matching pattern element
+        let step = match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p + 1, t));
+                p += 1;
+                continue
+            },
+            Some('?') => Some(1),
+            Some('[') => match parse_class(&pattern[p..], text[t]) {
+                Some((len, matched)) => matched.then_some(len),
+                // Unterminated class matches only a literal [
+                None => (text[t] == '[').then_some(1),
+            },
+            Some(&c) => (c == text[t]).then_some(1),
+            None => None,
+        };
+        match step {
+            Some(len) => {
+                p += len;
+                t += 1;
+            },
+            None => match backtrack {
+                Some((star_p, star_t)) => {
+                    backtrack = Some((star_p, star_t + 1));
+                    p = star_p;
+                    t = star_t + 1;
+                },
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// Parse the class at the start of the pattern, returning its length
+// and whether it matches the character, or None if it is unterminated
+fn parse_class(pattern: &[char], c: char) -> Option<(usize, bool)> {
+    let mut i = 1;
+    let negate = matches!(pattern.get(i), Some('!') | Some('^'));
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        match *pattern.get(i)? {
+            ']' if !first => break,
+            start => {
+                if pattern.get(i + 1) == Some(&'-')
+                    && pattern.get(i + 2).is_some_and(|&end| end != ']')
+                {
+                    matched |= (start..=pattern[i + 2]).contains(&c);
+                    i += 3;
+                } else {
+                    matched |= start == c;
+                    i += 1;
+                }
+            },
+        }
+        first = false;
+    }
+    Some((i + 1, matched != negate))
+}
+
+/// How specifically a pattern matches the text, None if it does not match.
+/// Besides globs, the pattern can be the first words of the text,
+/// such as the make and model part of a make-model-serial string.
+/// Higher values are more specific, with exact matches being the highest.
+pub fn match_specificity(pattern: &str, text: &str) -> Option<usize> {
+    if pattern == text {
+        return Some(usize::MAX)
+    }
+    if let Some(rest) = text.strip_prefix(pattern) {
+        if !pattern.is_empty() && rest.starts_with(' ') {
+            return Some(pattern.chars().count())
+        }
+    }
+    if is_glob(pattern) && glob_match(pattern, text) {
+        return Some(glob_literal_count(pattern))
+    }
+    None
+}
+
+// Count the characters a glob matches literally, counting classes as one
+fn glob_literal_count(pattern: &str) -> usize {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let mut count = 0;
+    let mut i = 0;
+    while i < pattern.len() {
+        match pattern[i] {
+            '*' | '?' => {
+                i += 1;
+                continue
+            },
+            '[' => {
+                i += parse_class(&pattern[i..], '\0')
+                    .map_or(1, |(len, _)| len);
+            },
+            _ => i += 1,
+        }
+        count += 1;
+    }
+    count
+}
+
+/// Pattern in a wallpaper file name matching workspace names
+#[derive(Clone)]
+pub enum NamePattern {
+    Glob(String),
+    /// Written with the re: prefix, matching the whole name
+    Regex(Regex),
+}
+
+impl NamePattern {
+    /// Returns None if the name is not a pattern
+    pub fn parse(name: &str) -> Option<anyhow::Result<NamePattern>> {
+        if let Some(regex) = name.strip_prefix("re:") {
+            return Some(Regex::new(&format!("^(?:{regex})$"))
+                .map(NamePattern::Regex)
+                .with_context(|| format!("Invalid regex in {name:?}")))
+        }
+        is_glob(name).then(|| Ok(NamePattern::Glob(name.to_string())))
+    }
+
+    /// How specifically the pattern matches the name, None if it does not.
+    /// Globs are ranked by their literally matched characters,
+    /// and any glob is more specific than a regex.
+    pub fn specificity(&self, name: &str) -> Option<usize> {
+        match self {
+            NamePattern::Glob(glob) => glob_match(glob, name)
+                .then(|| glob_literal_count(glob) + 1),
+            NamePattern::Regex(regex) => regex.is_match(name).then_some(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_wildcards() {
+        assert!(glob_match("DP-*", "DP-1"));
+        assert!(glob_match("DP-*", "DP-"));
+        assert!(!glob_match("DP-*", "eDP-1"));
+        assert!(glob_match("*-1", "HDMI-A-1"));
+        assert!(glob_match("a*b*c", "axxbyybzc"));
+        assert!(!glob_match("a*b*c", "axxbyyb"));
+        assert!(glob_match("DP-?", "DP-2"));
+        assert!(!glob_match("DP-?", "DP-10"));
+        assert!(glob_match("**", ""));
+    }
+
+    #[test]
+    fn glob_classes() {
+        assert!(glob_match("[abc]", "b"));
+        assert!(!glob_match("[abc]", "d"));
+        assert!(glob_match("ws[1-3]", "ws2"));
+        assert!(!glob_match("ws[1-3]", "ws4"));
+        assert!(glob_match("[!a]", "b"));
+        assert!(!glob_match("[!a]", "a"));
+        assert!(glob_match("[^a]", "b"));
+        // A ] first in the class is literal
+        assert!(glob_match("[]]", "]"));
+        assert!(glob_match("[]a]", "a"));
+        assert!(glob_match("[!]]", "a"));
+        assert!(!glob_match("[!]]", "]"));
+        // A - last in the class is literal
+        assert!(glob_match("[a-]", "-"));
+        // An unterminated class is a literal [
+        assert!(glob_match("[ab", "[ab"));
+        assert!(!glob_match("[ab", "a"));
+    }
+
+    #[test]
+    fn specificity_of_output_names() {
+        assert_eq!(match_specificity("DP-1", "DP-1"), Some(usize::MAX));
+        assert_eq!(match_specificity("Dell Inc.", "Dell Inc. U2720Q ABC"),
+            Some(9));
+        assert_eq!(match_specificity("Dell", "Dellx U2720Q ABC"), None);
+        assert_eq!(match_specificity("DP-*", "DP-1"), Some(3));
+        assert_eq!(match_specificity("[DH]*", "DP-1"), Some(1));
+        assert_eq!(match_specificity("HDMI-*", "DP-1"), None);
+    }
+
+    #[test]
+    fn name_patterns() {
+        assert!(NamePattern::parse("web").is_none());
+        assert!(NamePattern::parse("re:[").unwrap().is_err());
+        let glob = NamePattern::parse("web*").unwrap().unwrap();
+        let regex = NamePattern::parse("re:web|mail").unwrap().unwrap();
+        // Regexes match the whole name
+        assert_eq!(regex.specificity("web"), Some(0));
+        assert_eq!(regex.specificity("mail"), Some(0));
+        assert_eq!(regex.specificity("webmail"), None);
+        assert_eq!(glob.specificity("webmail"), Some(4));
+        assert_eq!(glob.specificity("mail"), None);
+        // Any glob ranks over a regex
+        let star = NamePattern::parse("*").unwrap().unwrap();
+        assert!(star.specificity("web") > regex.specificity("web"));
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    env,
+    fs,
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+
+/// Wallpaper override which is restored after a restart
+#[derive(Clone, Deserialize, Serialize)]
+pub struct PersistentOverride {
+    pub output: String,
+    pub workspace: Option<String>,
+    pub path: PathBuf,
+}
+
+/// Overrides saved to $XDG_STATE_HOME/multibg-wayland/overrides.json
+pub struct OverrideStore {
+    path: Option<PathBuf>,
+    overrides: Vec<PersistentOverride>,
+}
+
+impl OverrideStore {
+    pub fn load() -> OverrideStore {
+        let path = state_dir()
+            .map(|dir| dir.join(env!("CARGO_PKG_NAME")).join("overrides.json"));
+        let overrides = match path.as_deref().map(read_overrides) {
+            Some(Ok(overrides)) => overrides,
+            Some(Err(e)) => {
+                error!("{e:#}");
+                Vec::new()
+            },
+            None => Vec::new(),
+        };
+        OverrideStore { path, overrides }
+    }
+
+    pub fn for_output<'a>(
+        &'a self,
+        output_name: &'a str,
+    ) -> impl Iterator<Item = &'a PersistentOverride> {
+        self.overrides.iter()
+            .filter(move |o| o.output == output_name)
+    }
+
+    /// Save the override, replacing the one with the same scope
+    pub fn add(&mut self, persistent_override: PersistentOverride) {
+        self.overrides.retain(|o| o.output != persistent_override.output
+            || o.workspace != persistent_override.workspace);
+        self.overrides.push(persistent_override);
+        self.save();
+    }
+
+    /// Remove the overrides of a workspace, or all overrides of the output
+    /// if no workspace is given unless exact is set, or of all outputs
+    /// if no output is given. Returns the number of removed overrides.
+    pub fn remove(
+        &mut self,
+        output_name: Option<&str>,
+        workspace: Option<&str>,
+        exact: bool,
+    ) -> usize {
+        let count = self.overrides.len();
+        self.overrides.retain(|o| {
+            let output_matches = output_name
+                .is_none_or(|output_name| o.output == output_name);
+            let workspace_matches = (workspace.is_none() && !exact)
+                || o.workspace.as_deref() == workspace;
+            !(output_matches && workspace_matches)
+        });
+        let removed = count - self.overrides.len();
+        if removed > 0 {
+            self.save();
+        }
+        removed
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            error!("Cannot save overrides without XDG_STATE_HOME or HOME");
+            return
+        };
+        if let Err(e) = write_overrides(path, &self.overrides) {
+            error!("{e:#}");
+        }
+    }
+}
+
+fn state_dir() -> Option<PathBuf> {
+    env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME")
+            .map(|home| Path::new(&home).join(".local/state"))
+        )
+}
+
+fn read_overrides(path: &Path) -> anyhow::Result<Vec<PersistentOverride>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return Ok(Vec::new())
+        },
+        Err(e) => return Err(e).with_context(||
+            format!("Failed to read saved overrides {path:?}")),
+    };
+    let overrides: Vec<PersistentOverride> = serde_json::from_str(&text)
+        .with_context(|| format!("Failed to parse saved overrides {path:?}"))?;
+    debug!("Loaded {} saved overrides from {path:?}", overrides.len());
+    Ok(overrides)
+}
+
+fn write_overrides(
+    path: &Path,
+    overrides: &[PersistentOverride],
+) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(||
+            format!("Failed to create directory {dir:?}"))?;
+    }
+    // Replace atomically by renaming a temporary file over it
+    let tmp_path = path.with_extension("json.tmp");
+    let json = serde_json::to_string_pretty(overrides).unwrap();
+    fs::write(&tmp_path, json).with_context(||
+        format!("Failed to write saved overrides {tmp_path:?}"))?;
+    fs::rename(&tmp_path, path).with_context(||
+        format!("Failed to replace saved overrides {path:?}"))?;
+    debug!("Saved {} overrides to {path:?}", overrides.len());
+    Ok(())
+}
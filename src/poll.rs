@@ -3,6 +3,8 @@ use std::{
     marker::PhantomData,
     mem::MaybeUninit,
     os::fd::{BorrowedFd, OwnedFd},
+    rc::Rc,
+    time::Instant,
 };
 
 use rustix::{
@@ -11,6 +13,10 @@ use rustix::{
     fs::{fcntl_setfl, OFlags},
     io::{Errno, fcntl_setfd, FdFlags, read, retry_on_intr, write},
     pipe::pipe,
+    time::{
+        Itimerspec, Timespec, TimerfdClockId, TimerfdFlags, TimerfdTimerFlags,
+        timerfd_create, timerfd_settime,
+    },
 };
 
 pub struct Poll<'fd> {
@@ -104,6 +110,53 @@ impl AsFd for Waker {
     }
 }
 
+/// Timer armed to a single deadline at a time,
+/// readable once the deadline has passed
+pub struct Timer {
+    fd: Rc<OwnedFd>,
+}
+
+impl Timer {
+    pub fn new() -> io::Result<Timer> {
+        let fd = timerfd_create(
+            TimerfdClockId::Monotonic,
+            TimerfdFlags::CLOEXEC | TimerfdFlags::NONBLOCK,
+        )?;
+        Ok(Timer { fd: Rc::new(fd) })
+    }
+
+    pub fn fd(&self) -> Rc<OwnedFd> {
+        Rc::clone(&self.fd)
+    }
+
+    /// Arm the timer to the deadline, or disarm it with None
+    pub fn set_deadline(&self, deadline: Option<Instant>) {
+        let zero = Timespec { tv_sec: 0, tv_nsec: 0 };
+        let value = match deadline {
+            None => zero,
+            Some(deadline) => {
+                let duration = deadline.saturating_duration_since(
+                    Instant::now()
+                );
+                Timespec {
+                    tv_sec: duration.as_secs() as _,
+                    // A zero value would disarm the timer
+                    tv_nsec: duration.subsec_nanos().max(1) as _,
+                }
+            },
+        };
+        let new_value = Itimerspec { it_interval: zero, it_value: value };
+        timerfd_settime(&*self.fd, TimerfdTimerFlags::empty(), &new_value)
+            .expect("Failed to set timer");
+    }
+
+    pub fn read(&self) {
+        assert_ok_or_wouldblock(
+            read(&*self.fd, &mut [MaybeUninit::<u8>::uninit(); 8])
+        );
+    }
+}
+
 pub fn pipe_cloexec_nonblock() -> io::Result<(OwnedFd, OwnedFd)> {
     #[cfg(any(
         target_os = "linux",
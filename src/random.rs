@@ -0,0 +1,220 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use crate::{image::WallpaperFile, slideshow::Rng};
+
+/// Images of the _random pool of an output, one of them picked each time
+/// a workspace without its own wallpaper becomes visible, keeping only
+/// the most recently shown ones decoded
+pub struct RandomPool<T> {
+    files: Vec<WallpaperFile>,
+    /// Decoded images by their index in files, the most recently used last
+    cache: Vec<(usize, T)>,
+    cache_size: usize,
+    /// Keep the first pick of each workspace for the session
+    stable: bool,
+    /// Paths of the picks by workspace name, to survive reloads
+    picks: HashMap<String, PathBuf>,
+    /// Index of the image picked last and the workspace it was for
+    current: Option<(usize, String)>,
+    rng: Rng,
+}
+
+impl<T: Clone> RandomPool<T> {
+    pub fn new(
+        files: Vec<WallpaperFile>,
+        cache_size: usize,
+        stable: bool,
+    ) -> RandomPool<T> {
+        RandomPool {
+            files,
+            cache: Vec::new(),
+            cache_size,
+            stable,
+            picks: HashMap::new(),
+            current: None,
+            rng: Rng::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn cached_images(&self) -> impl Iterator<Item = &T> {
+        self.cache.iter().map(|(_, image)| image)
+    }
+
+    pub fn file(&self, index: usize) -> &WallpaperFile {
+        &self.files[index]
+    }
+
+    /// Keep the picks from the previous pool of the output
+    /// for the images which are still in this one
+    pub fn continue_from(&mut self, previous: &RandomPool<T>) {
+        if self.stable {
+            self.picks.clone_from(&previous.picks);
+        }
+        self.current = previous.current.as_ref()
+            .and_then(|(index, workspace_name)| {
+                let path = &previous.files[*index].path;
+                let index = self.files.iter()
+                    .position(|file| &file.path == path)?;
+                Some((index, workspace_name.clone()))
+            });
+    }
+
+    /// Index of the image to show on a workspace, a new random one
+    /// unless it is still the same visit of the workspace
+    pub fn pick(
+        &mut self,
+        workspace_name: &str,
+        newly_visible: bool,
+    ) -> Option<usize> {
+        let len = self.files.len();
+        if len == 0 {
+            return None
+        }
+        if let Some((index, current_workspace)) = &self.current {
+            if !newly_visible && current_workspace == workspace_name {
+                return Some(*index)
+            }
+        }
+        let picked = self.picks.get(workspace_name)
+            .filter(|_| self.stable)
+            .and_then(|path|
+                self.files.iter().position(|file| &file.path == path)
+            );
+        if let Some(index) = picked {
+            self.current = Some((index, workspace_name.to_string()));
+            return picked
+        }
+        let mut index = self.rng.below(len);
+        // Avoid showing the same image twice in a row
+        if len > 1 && self.current.as_ref().is_some_and(|(current, _)|
+            *current == index
+        ) {
+            index = (index + 1 + self.rng.below(len - 1)) % len;
+        }
+        if self.stable {
+            self.picks.insert(
+                workspace_name.to_string(),
+                self.files[index].path.clone(),
+            );
+        }
+        self.current = Some((index, workspace_name.to_string()));
+        Some(index)
+    }
+
+    /// The decoded image if cached, marking it as the most recently used
+    pub fn cached(&mut self, index: usize) -> Option<T> {
+        let position = self.cache.iter().position(|(i, _)| *i == index)?;
+        let entry = self.cache.remove(position);
+        let image = entry.1.clone();
+        self.cache.push(entry);
+        Some(image)
+    }
+
+    /// Cache a decoded image, evicting the least recently used one if full
+    pub fn insert(&mut self, index: usize, image: T) {
+        if self.cache.len() >= self.cache_size {
+            self.cache.remove(0);
+        }
+        self.cache.push((index, image));
+    }
+
+    /// Drop an image which failed to load from the pool
+    pub fn remove(&mut self, index: usize) -> WallpaperFile {
+        let file = self.files.remove(index);
+        self.cache.retain(|(i, _)| *i != index);
+        for (i, _) in &mut self.cache {
+            if *i > index {
+                *i -= 1;
+            }
+        }
+        self.current = match self.current.take() {
+            Some((current, _)) if current == index => None,
+            Some((current, workspace_name)) if current > index => {
+                Some((current - 1, workspace_name))
+            },
+            current => current,
+        };
+        file
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pool(names: &[&str], cache_size: usize, stable: bool)
+        -> RandomPool<&'static str>
+    {
+        let files = names.iter()
+            .map(|name| WallpaperFile {
+                path: PathBuf::from(format!("/_random/{name}.jpg")),
+                workspace: "_random".to_string(),
+                workspace_number: -1,
+                variant: None,
+                occupied: false,
+                canon_path: PathBuf::from(format!("/_random/{name}.jpg")),
+                canon_modified: 0,
+                pattern: None,
+                fit: None,
+                background_color: None,
+            })
+            .collect();
+        RandomPool::new(files, cache_size, stable)
+    }
+
+    #[test]
+    fn not_the_same_image_twice_in_a_row() {
+        let mut random = pool(&["a", "b", "c"], 4, false);
+        let mut previous = random.pick("1", true).unwrap();
+        for workspace in ["2", "1", "3"].repeat(100) {
+            let index = random.pick(workspace, true).unwrap();
+            assert_ne!(index, previous);
+            // The same visit keeps showing it
+            assert_eq!(random.pick(workspace, false), Some(index));
+            previous = index;
+        }
+        // Without another image to pick
+        let mut single = pool(&["a"], 4, false);
+        assert_eq!(single.pick("1", true), Some(0));
+        assert_eq!(single.pick("1", true), Some(0));
+        assert_eq!(pool(&[], 4, false).pick("1", true), None);
+    }
+
+    #[test]
+    fn stable_picks() {
+        let mut stable = pool(&["a", "b", "c", "d"], 4, true);
+        let first = stable.pick("1", true).unwrap();
+        for workspace in ["2", "3"].repeat(20) {
+            stable.pick(workspace, true);
+            assert_eq!(stable.pick("1", true), Some(first));
+        }
+        // Across reloads while the image stays in the pool
+        let path = stable.file(first).path.clone();
+        let mut reloaded = pool(&["e", "d", "c", "b", "a"], 4, true);
+        reloaded.continue_from(&stable);
+        let index = reloaded.pick("1", true).unwrap();
+        assert_eq!(reloaded.file(index).path, path);
+    }
+
+    #[test]
+    fn least_recently_used_evicted() {
+        let mut pool = pool(&["a", "b", "c", "d"], 2, false);
+        pool.insert(0, "a");
+        pool.insert(1, "b");
+        assert_eq!(pool.cached(0), Some("a"));
+        pool.insert(2, "c");
+        assert_eq!(pool.cached(1), None);
+        assert_eq!(pool.cached(0), Some("a"));
+        assert_eq!(pool.cached(2), Some("c"));
+        assert_eq!(pool.cached_images().count(), 2);
+        // Removing an image shifts the indices of the ones after it
+        assert_eq!(pool.remove(1).path, PathBuf::from("/_random/b.jpg"));
+        assert_eq!(pool.len(), 3);
+        assert_eq!(pool.cached(0), Some("a"));
+        assert_eq!(pool.cached(1), Some("c"));
+    }
+}
@@ -0,0 +1,353 @@
+use std::{
+    f64::consts::PI,
+    fmt,
+    mem::MaybeUninit,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail};
+use serde::Deserialize;
+
+/// Variant of a wallpaper for a part of the day, marked by a suffix
+/// of the workspace name, such as 1.night.jpg
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeOfDay {
+    Day,
+    Dusk,
+    Night,
+}
+
+impl TimeOfDay {
+    /// Split the time of day suffix from a workspace name
+    pub fn split_suffix(workspace: &str) -> (&str, Option<TimeOfDay>) {
+        let Some((name, suffix)) = workspace.rsplit_once('.') else {
+            return (workspace, None)
+        };
+        let time_of_day = match suffix {
+            "day" => TimeOfDay::Day,
+            "dusk" => TimeOfDay::Dusk,
+            "night" => TimeOfDay::Night,
+            _ => return (workspace, None),
+        };
+        if name.is_empty() {
+            return (workspace, None)
+        }
+        (name, Some(time_of_day))
+    }
+
+    /// Variants to show in the order of preference,
+    /// where None is the wallpaper without a variant
+    pub fn preference(self) -> [Option<TimeOfDay>; 4] {
+        use TimeOfDay::*;
+        match self {
+            Day => [Some(Day), None, Some(Dusk), Some(Night)],
+            Dusk => [Some(Dusk), None, Some(Night), Some(Day)],
+            Night => [Some(Night), None, Some(Dusk), Some(Day)],
+        }
+    }
+}
+
+impl fmt::Display for TimeOfDay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TimeOfDay::Day => "day",
+            TimeOfDay::Dusk => "dusk",
+            TimeOfDay::Night => "night",
+        })
+    }
+}
+
+/// Time on the local clock as minutes after midnight, parsed from HH:MM
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct ClockTime(u32);
+
+impl FromStr for ClockTime {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<ClockTime> {
+        let err = || anyhow!("Invalid time '{s}', expected HH:MM");
+        let (hours, minutes) = s.split_once(':').ok_or_else(err)?;
+        let hours: u32 = hours.parse().map_err(|_| err())?;
+        let minutes: u32 = minutes.parse().map_err(|_| err())?;
+        if hours > 23 || minutes > 59 {
+            return Err(err())
+        }
+        Ok(ClockTime(hours * 60 + minutes))
+    }
+}
+
+impl TryFrom<String> for ClockTime {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> anyhow::Result<ClockTime> {
+        s.parse()
+    }
+}
+
+/// Settings of the schedule from the command line or the configuration file
+#[derive(Clone, Copy, Default)]
+pub struct ScheduleOptions {
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub day_start: Option<ClockTime>,
+    pub dusk_start: Option<ClockTime>,
+    pub night_start: Option<ClockTime>,
+}
+
+impl ScheduleOptions {
+    // Options set here take precedence over the ones set in other
+    pub fn or(self, other: ScheduleOptions) -> ScheduleOptions {
+        ScheduleOptions {
+            latitude: self.latitude.or(other.latitude),
+            longitude: self.longitude.or(other.longitude),
+            day_start: self.day_start.or(other.day_start),
+            dusk_start: self.dusk_start.or(other.dusk_start),
+            night_start: self.night_start.or(other.night_start),
+        }
+    }
+}
+
+/// When to show the day, dusk and night variants of the wallpapers
+#[derive(Debug)]
+pub enum Schedule {
+    /// Starts of the times of day on the local clock in order
+    Fixed([(ClockTime, TimeOfDay); 3]),
+    /// Day while the sun is 6° above the horizon, night while it is
+    /// 6° below, dusk in between in the evening and also at dawn
+    Solar { latitude: f64, longitude: f64 },
+}
+
+impl Schedule {
+    pub fn new(options: ScheduleOptions) -> anyhow::Result<Schedule> {
+        let (latitude, longitude) = match (options.latitude, options.longitude)
+        {
+            (Some(latitude), Some(longitude)) => (latitude, longitude),
+            (None, None) => {
+                let mut starts = [
+                    (options.day_start.unwrap_or(ClockTime(7 * 60)),
+                        TimeOfDay::Day),
+                    (options.dusk_start.unwrap_or(ClockTime(19 * 60)),
+                        TimeOfDay::Dusk),
+                    (options.night_start.unwrap_or(ClockTime(21 * 60)),
+                        TimeOfDay::Night),
+                ];
+                starts.sort_by_key(|(start, _)| start.0);
+                return Ok(Schedule::Fixed(starts))
+            },
+            _ => bail!("Options latitude and longitude must be set together"),
+        };
+        if !(-90.0..=90.0).contains(&latitude) {
+            bail!("Option latitude must be between -90 and 90");
+        }
+        if !(-180.0..=180.0).contains(&longitude) {
+            bail!("Option longitude must be between -180 and 180");
+        }
+        if options.day_start.is_some()
+            || options.dusk_start.is_some()
+            || options.night_start.is_some()
+        {
+            bail!("Options day-start, dusk-start and night-start cannot be \
+                used with latitude and longitude");
+        }
+        Ok(Schedule::Solar { latitude, longitude })
+    }
+
+    /// The time of day now and how long until it changes,
+    /// None if it will not change in the next days
+    pub fn current(&self) -> (TimeOfDay, Option<Duration>) {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        match *self {
+            Schedule::Fixed(starts) => fixed_current(starts, now),
+            Schedule::Solar { latitude, longitude } => {
+                solar_current(latitude, longitude, now.as_secs_f64())
+            },
+        }
+    }
+}
+
+fn fixed_current(
+    starts: [(ClockTime, TimeOfDay); 3],
+    now: Duration,
+) -> (TimeOfDay, Option<Duration>) {
+    const DAY: u64 = 24 * 60 * 60;
+    let now_secs = local_seconds_of_day(now.as_secs() as libc::time_t);
+    let time_of_day = starts.iter()
+        .rev()
+        .find(|(start, _)| start.0 as u64 * 60 <= now_secs)
+        // From the previous day
+        .unwrap_or(&starts[2])
+        .1;
+    let next_secs = starts.iter()
+        .map(|(start, _)| start.0 as u64 * 60)
+        .find(|&start_secs| start_secs > now_secs)
+        .unwrap_or(starts[0].0.0 as u64 * 60 + DAY);
+    (time_of_day, Some(Duration::from_secs(next_secs - now_secs)))
+}
+
+unsafe extern "C" {
+    // POSIX but missing from the libc crate
+    fn tzset();
+}
+
+fn local_seconds_of_day(time: libc::time_t) -> u64 {
+    let mut tm = MaybeUninit::<libc::tm>::uninit();
+    let tm = unsafe {
+        // Follow changes of the time zone, which localtime_r may not
+        tzset();
+        if libc::localtime_r(&time, tm.as_mut_ptr()).is_null() {
+            // Fall back to UTC
+            return time.rem_euclid(24 * 60 * 60) as u64
+        }
+        tm.assume_init()
+    };
+    tm.tm_hour as u64 * 3600 + tm.tm_min as u64 * 60 + tm.tm_sec as u64
+}
+
+// Sunrise equation from https://en.wikipedia.org/wiki/Sunrise_equation
+// with the times in Julian days, accurate to a minute or two,
+// generalized to times when the sun is at an elevation other than
+// the horizon to find the twilights
+fn solar_current(
+    latitude: f64,
+    longitude: f64,
+    now: f64,
+) -> (TimeOfDay, Option<Duration>) {
+    const ELEVATION: f64 = 6.0;
+    let julian_now = now / 86400.0 + 2440587.5;
+    let today = (julian_now - 2451545.0 + 0.0008).ceil();
+    let phi = latitude.to_radians();
+    // Changes of the time of day as (Julian day, time of day after it)
+    let mut changes = Vec::new();
+    let mut elevation_now = None;
+    for day in [today - 1.0, today, today + 1.0] {
+        let mean_solar_time = day - longitude / 360.0;
+        let anomaly = (357.5291 + 0.98560028 * mean_solar_time)
+            .rem_euclid(360.0).to_radians();
+        let center = 1.9148 * anomaly.sin()
+            + 0.0200 * (2.0 * anomaly).sin()
+            + 0.0003 * (3.0 * anomaly).sin();
+        let ecliptic_longitude = (anomaly.to_degrees() + center + 180.0
+            + 102.9372).rem_euclid(360.0).to_radians();
+        let transit = 2451545.0 + mean_solar_time
+            + 0.0053 * anomaly.sin()
+            - 0.0069 * (2.0 * ecliptic_longitude).sin();
+        let declination = (ecliptic_longitude.sin()
+            * 23.4397f64.to_radians().sin()).asin();
+        if day == today {
+            let hour_angle = (julian_now - transit) * 2.0 * PI;
+            elevation_now = Some((phi.sin() * declination.sin()
+                + phi.cos() * declination.cos() * hour_angle.cos())
+                .asin().to_degrees());
+        }
+        for (elevation, rising, setting) in [
+            (-ELEVATION, TimeOfDay::Dusk, TimeOfDay::Night),
+            (ELEVATION, TimeOfDay::Day, TimeOfDay::Dusk),
+        ] {
+            let cos_hour_angle = (elevation.to_radians().sin()
+                - phi.sin() * declination.sin())
+                / (phi.cos() * declination.cos());
+            // The sun does not cross this elevation on this day
+            if !(-1.0..=1.0).contains(&cos_hour_angle) {
+                continue
+            }
+            // In days from the transit
+            let half_arc = cos_hour_angle.acos() / (2.0 * PI);
+            changes.push((transit - half_arc, rising));
+            changes.push((transit + half_arc, setting));
+        }
+    }
+    changes.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let time_of_day = changes.iter()
+        .rev()
+        .find(|(time, _)| *time <= julian_now)
+        .map(|(_, time_of_day)| *time_of_day)
+        .unwrap_or_else(|| match elevation_now.unwrap() {
+            elevation if elevation >= ELEVATION => TimeOfDay::Day,
+            elevation if elevation >= -ELEVATION => TimeOfDay::Dusk,
+            _ => TimeOfDay::Night,
+        });
+    let next_change = changes.iter()
+        .find(|(time, _)| *time > julian_now)
+        .map(|(time, _)| Duration::from_secs_f64(
+            (time - julian_now) * 86400.0
+        ));
+    (time_of_day, next_change)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-06-21 00:00 UTC
+    const SOLSTICE: f64 = 1718928000.0;
+    const HOUR: f64 = 3600.0;
+    const LONDON: (f64, f64) = (51.48, 0.0);
+
+    fn time_of_day_at(
+        (latitude, longitude): (f64, f64),
+        now: f64,
+    ) -> (TimeOfDay, Option<Duration>) {
+        solar_current(latitude, longitude, now)
+    }
+
+    #[test]
+    fn summer_day_in_london() {
+        let (time_of_day, next) =
+            time_of_day_at(LONDON, SOLSTICE + 12.0 * HOUR);
+        assert_eq!(time_of_day, TimeOfDay::Day);
+        // The sun sets below 6° around 19:30 UTC
+        let next = next.unwrap().as_secs_f64();
+        assert!((7.0 * HOUR..8.0 * HOUR).contains(&next), "{next}");
+        assert_eq!(
+            time_of_day_at(LONDON, SOLSTICE + 20.5 * HOUR).0,
+            TimeOfDay::Dusk,
+        );
+        assert_eq!(
+            time_of_day_at(LONDON, SOLSTICE + 23.5 * HOUR).0,
+            TimeOfDay::Night,
+        );
+        // Dawn is dusk as well
+        assert_eq!(
+            time_of_day_at(LONDON, SOLSTICE + 3.5 * HOUR).0,
+            TimeOfDay::Dusk,
+        );
+    }
+
+    #[test]
+    fn changes_at_the_boundaries() {
+        let mut now = SOLSTICE + 12.0 * HOUR;
+        let mut seen = Vec::new();
+        for _ in 0..4 {
+            let (time_of_day, next) = time_of_day_at(LONDON, now);
+            let next = next.unwrap().as_secs_f64();
+            // Still the same just before the change
+            assert_eq!(time_of_day_at(LONDON, now + next - 60.0).0,
+                time_of_day);
+            now += next + 60.0;
+            seen.push(time_of_day_at(LONDON, now).0);
+        }
+        assert_eq!(seen, [
+            TimeOfDay::Dusk,
+            TimeOfDay::Night,
+            TimeOfDay::Dusk,
+            TimeOfDay::Day,
+        ]);
+    }
+
+    #[test]
+    fn polar_day_and_night() {
+        const SVALBARD: (f64, f64) = (78.22, 15.65);
+        assert_eq!(
+            time_of_day_at(SVALBARD, SOLSTICE),
+            (TimeOfDay::Day, None),
+        );
+        // 2024-12-21 12:00 UTC
+        assert_eq!(
+            time_of_day_at(SVALBARD, 1734782400.0),
+            (TimeOfDay::Night, None),
+        );
+    }
+}
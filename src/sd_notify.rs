@@ -0,0 +1,122 @@
+use std::{
+    env,
+    ffi::OsString,
+    os::unix::net::{SocketAddr, UnixDatagram},
+    process,
+    time::Duration,
+};
+
+use anyhow::Context;
+use log::{debug, error};
+
+use crate::wayland::BackgroundLayer;
+
+/// Service manager notifications over the datagram socket in $NOTIFY_SOCKET,
+/// same as sd_notify(3) of systemd
+pub struct SdNotify {
+    socket: UnixDatagram,
+    addr: SocketAddr,
+    watchdog_interval: Option<Duration>,
+    /// Outputs at startup to show their first wallpaper before READY=1
+    startup_outputs: Option<Vec<String>>,
+}
+
+impl SdNotify {
+    /// None if not started by a service manager expecting notifications
+    pub fn from_env() -> Option<SdNotify> {
+        let path = env::var_os("NOTIFY_SOCKET")?;
+        let watchdog_usec = env::var("WATCHDOG_USEC").ok();
+        let watchdog_pid = env::var("WATCHDOG_PID").ok();
+        // Not for our children, such as the on-change hook commands
+        env::remove_var("NOTIFY_SOCKET");
+        env::remove_var("WATCHDOG_USEC");
+        env::remove_var("WATCHDOG_PID");
+        let addr = match socket_addr(path) {
+            Ok(addr) => addr,
+            Err(e) => {
+                error!("Invalid NOTIFY_SOCKET: {e:#}");
+                return None
+            }
+        };
+        let socket = match UnixDatagram::unbound() {
+            Ok(socket) => socket,
+            Err(e) => {
+                error!("Failed to create socket for service manager \
+                    notifications: {e}");
+                return None
+            }
+        };
+        let watchdog_interval = watchdog_usec
+            .filter(|_| watchdog_pid.is_none_or(|pid|
+                pid.parse() == Ok(process::id())
+            ))
+            .and_then(|usec| usec.parse::<u64>().ok())
+            .filter(|&usec| usec > 0)
+            // Ping twice in the period as recommended
+            .map(|usec| Duration::from_micros(usec / 2));
+        debug!("Sending service manager notifications, watchdog interval: \
+            {watchdog_interval:?}");
+        Some(SdNotify {
+            socket,
+            addr,
+            watchdog_interval,
+            startup_outputs: None,
+        })
+    }
+
+    pub fn notify(&self, message: &str) {
+        if let Err(e) = self.socket.send_to_addr(message.as_bytes(), &self.addr)
+        {
+            error!("Failed to send service manager notification \
+                {message:?}: {e}");
+        }
+    }
+
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        self.watchdog_interval
+    }
+
+    /// Remember the outputs which have to show their wallpapers first
+    pub fn set_startup_outputs(&mut self, bg_layers: &[BackgroundLayer]) {
+        self.startup_outputs = Some(bg_layers.iter()
+            .map(|bg_layer| bg_layer.output_name.clone())
+            .collect());
+    }
+
+    /// Send READY=1 once all the outputs which existed at startup
+    /// have committed their first wallpaper, or have none to show
+    pub fn check_ready(&mut self, bg_layers: &[BackgroundLayer]) {
+        let Some(startup_outputs) = self.startup_outputs.as_mut() else {
+            return
+        };
+        startup_outputs.retain(|output_name| bg_layers.iter()
+            .find(|bg_layer| &bg_layer.output_name == output_name)
+            .is_some_and(|bg_layer| !bg_layer.is_settled())
+        );
+        if startup_outputs.is_empty() {
+            debug!("All outputs show their wallpapers, notifying readiness");
+            self.notify("READY=1");
+            self.startup_outputs = None;
+        }
+    }
+}
+
+fn socket_addr(path: OsString) -> anyhow::Result<SocketAddr> {
+    if let Some(name) = path.to_str().and_then(|s| s.strip_prefix('@')) {
+        return abstract_socket_addr(name)
+    }
+    SocketAddr::from_pathname(&path)
+        .with_context(|| format!("Invalid socket path {path:?}"))
+}
+
+#[cfg(target_os = "linux")]
+fn abstract_socket_addr(name: &str) -> anyhow::Result<SocketAddr> {
+    use std::os::linux::net::SocketAddrExt;
+    SocketAddr::from_abstract_name(name)
+        .with_context(|| format!("Invalid abstract socket name {name:?}"))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn abstract_socket_addr(name: &str) -> anyhow::Result<SocketAddr> {
+    anyhow::bail!("Abstract socket {name:?} is not supported on this platform")
+}
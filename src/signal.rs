@@ -9,7 +9,7 @@ use std::{
 
 use libc::{
     raise, SA_RESETHAND, SA_RESTART, SIG_DFL, SIG_ERR,
-    SIGHUP, SIGINT, SIGUSR1, SIGUSR2, SIGTERM,
+    SIGHUP, SIGINT, SIGRTMIN, SIGUSR1, SIGUSR2, SIGTERM,
     sigaction, sigemptyset, signal, sigset_t, write,
 };
 use rustix::{
@@ -20,13 +20,19 @@ use rustix::{
 use crate::poll::pipe_cloexec_nonblock;
 
 const TERM_SIGNALS: [c_int; 3] = [SIGHUP, SIGINT, SIGTERM];
-const OTHER_SIGNALS: [c_int; 2] = [SIGUSR1, SIGUSR2];
+
+// Pausing is on the first real-time signal, which unlike TSTP
+// has no job control meaning. SIGRTMIN is not a constant with libc.
+fn other_signals() -> [c_int; 3] {
+    [SIGUSR1, SIGUSR2, SIGRTMIN()]
+}
 
 const TERM: u8 = 1 << 0;
 const INT: u8 = 1 << 1;
 const HUP: u8 = 1 << 2;
 const USR1: u8 = 1 << 3;
 const USR2: u8 = 1 << 4;
+const RTMIN: u8 = 1 << 5;
 
 static PIPE_FD: AtomicI32 = AtomicI32::new(-1);
 
@@ -55,7 +61,7 @@ impl SignalPipe {
                     SA_RESTART | SA_RESETHAND,
                 )?;
             }
-            for signum in OTHER_SIGNALS {
+            for signum in other_signals() {
                 sigaction_set_handler(
                     signum,
                     handle_other_signals,
@@ -80,7 +86,7 @@ impl SignalPipe {
 
 impl Drop for SignalPipe {
     fn drop(&mut self) {
-        for signum in OTHER_SIGNALS {
+        for signum in other_signals() {
             sigaction_reset_default(signum).unwrap();
         }
         for signum in TERM_SIGNALS {
@@ -119,6 +125,9 @@ impl SignalFlags {
     pub fn has_usr2(self) -> bool {
         self.0 & USR2 != 0
     }
+    pub fn has_rtmin(self) -> bool {
+        self.0 & RTMIN != 0
+    }
 }
 
 fn sigset_empty() -> io::Result<sigset_t> {
@@ -189,6 +198,7 @@ extern "C" fn handle_other_signals(signum: c_int) {
         let byte: u8 = match signum {
             SIGUSR1 => USR1,
             SIGUSR2 => USR2,
+            _ if signum == SIGRTMIN() => RTMIN,
             _ => 0,
         };
         // In case of an error ignore non-termination signals
@@ -0,0 +1,277 @@
+use std::{
+    process,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Deserialize;
+
+use crate::schedule::TimeOfDay;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SlideshowOrder {
+    /// In the order of the file names
+    Sorted,
+    /// In random order, shuffled again after each round
+    Shuffle,
+}
+
+/// Rotation state of the images of a single workspace
+pub struct Slideshow {
+    pub workspace_name: String,
+    pub variant: Option<TimeOfDay>,
+    pub occupied: bool,
+    order: Vec<usize>,
+    position: usize,
+    shuffle: bool,
+    interval: Duration,
+    next_switch: Instant,
+}
+
+impl Slideshow {
+    pub fn new(
+        workspace_name: String,
+        variant: Option<TimeOfDay>,
+        occupied: bool,
+        len: usize,
+        order: SlideshowOrder,
+        interval: Duration,
+        rng: &mut Rng,
+    ) -> Slideshow {
+        let shuffle = order == SlideshowOrder::Shuffle;
+        let mut order = (0..len).collect::<Vec<_>>();
+        if shuffle {
+            rng.shuffle(&mut order);
+        }
+        Slideshow {
+            workspace_name,
+            variant,
+            occupied,
+            order,
+            position: 0,
+            shuffle,
+            interval,
+            next_switch: Instant::now() + interval,
+        }
+    }
+
+    /// Whether these are the images of the workspace variant,
+    /// separate for each time of day and with or without windows
+    pub fn shows(
+        &self,
+        workspace_name: &str,
+        variant: Option<TimeOfDay>,
+        occupied: bool,
+    ) -> bool {
+        self.workspace_name == workspace_name
+            && self.variant == variant
+            && self.occupied == occupied
+    }
+
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    /// Index of the currently shown image among the images of the workspace
+    pub fn current(&self) -> usize {
+        self.order[self.position]
+    }
+
+    pub fn next_switch(&self) -> Instant {
+        self.next_switch
+    }
+
+    /// Keep the position in the rotation from a previous slideshow
+    /// of the same images, but with the new order and interval
+    pub fn continue_from(&mut self, previous: &Slideshow) {
+        if previous.len() != self.len() {
+            return
+        }
+        if previous.shuffle == self.shuffle {
+            self.order.clone_from(&previous.order);
+            self.position = previous.position;
+        } else {
+            let current = previous.current();
+            self.position = self.order.iter()
+                .position(|&index| index == current)
+                .unwrap();
+        }
+        self.next_switch = previous.next_switch
+            .min(Instant::now() + self.interval);
+    }
+
+    /// Switch to the next image if its time has come,
+    /// returns whether the current image has changed
+    pub fn advance(&mut self, now: Instant, rng: &mut Rng) -> bool {
+        if now < self.next_switch {
+            return false
+        }
+        let previous = self.current();
+        // Skip the missed switches if we were suspended for a long time
+        while self.next_switch <= now {
+            self.next_switch += self.interval;
+        }
+        self.position += 1;
+        if self.position == self.order.len() {
+            self.position = 0;
+            if self.shuffle {
+                rng.shuffle(&mut self.order);
+                // Avoid showing the same image twice in a row
+                if self.order.len() > 1 && self.order[0] == previous {
+                    let last = self.order.len() - 1;
+                    self.order.swap(0, last);
+                }
+            }
+        }
+        self.current() != previous
+    }
+}
+
+/// Small xorshift* pseudo random number generator,
+/// good enough to shuffle wallpapers
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new() -> Rng {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_nanos() as u64)
+            .unwrap_or_default();
+        let seed = nanos ^ (process::id() as u64).rotate_left(32);
+        // State must not be zero
+        Rng { state: seed | 1 }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Random number in range [0, bound)
+    pub fn below(&mut self, bound: usize) -> usize {
+        (self.next_u64() % bound as u64) as usize
+    }
+
+    // Fisher-Yates shuffle
+    pub fn shuffle<T>(&mut self, slice: &mut [T]) {
+        for i in (1..slice.len()).rev() {
+            let j = self.below(i + 1);
+            slice.swap(i, j);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTERVAL: Duration = Duration::from_secs(60);
+
+    fn slideshow(
+        occupied: bool,
+        len: usize,
+        order: SlideshowOrder,
+    ) -> Slideshow {
+        Slideshow::new(
+            "1".to_string(),
+            None,
+            occupied,
+            len,
+            order,
+            INTERVAL,
+            &mut Rng::new(),
+        )
+    }
+
+    #[test]
+    fn separate_slideshows_with_and_without_windows() {
+        let slideshows = [
+            slideshow(false, 2, SlideshowOrder::Sorted),
+            slideshow(true, 3, SlideshowOrder::Sorted),
+        ];
+        let find = |occupied| slideshows.iter()
+            .find(|slideshow| slideshow.shows("1", None, occupied))
+            .unwrap();
+        assert_eq!(find(false).len(), 2);
+        assert!(!find(false).occupied);
+        assert_eq!(find(true).len(), 3);
+        assert!(find(true).occupied);
+        assert!(!slideshows.iter().any(|slideshow|
+            slideshow.shows("1", Some(TimeOfDay::Night), false)
+        ));
+        assert!(!slideshows.iter().any(|slideshow|
+            slideshow.shows("2", None, false)
+        ));
+    }
+    // The images shown from now on at each switch
+    fn shown(slideshow: &mut Slideshow, rng: &mut Rng, count: usize)
+        -> Vec<usize>
+    {
+        let mut shown = vec![slideshow.current()];
+        for _ in 1..count {
+            let now = slideshow.next_switch();
+            slideshow.advance(now, rng);
+            shown.push(slideshow.current());
+        }
+        shown
+    }
+
+    #[test]
+    fn sorted_order_wraps_around() {
+        let mut rng = Rng::new();
+        let mut sorted = slideshow(false, 3, SlideshowOrder::Sorted);
+        assert!(!sorted.advance(Instant::now(), &mut rng));
+        assert_eq!(shown(&mut sorted, &mut rng, 7), [0, 1, 2, 0, 1, 2, 0]);
+        // A single image never changes
+        let mut single = slideshow(false, 1, SlideshowOrder::Sorted);
+        assert!(!single.advance(single.next_switch(), &mut rng));
+        assert_eq!(single.current(), 0);
+    }
+
+    #[test]
+    fn missed_switches_are_skipped() {
+        let mut rng = Rng::new();
+        let mut sorted = slideshow(false, 3, SlideshowOrder::Sorted);
+        let now = sorted.next_switch() + INTERVAL * 10;
+        assert!(sorted.advance(now, &mut rng));
+        assert_eq!(sorted.current(), 1);
+        assert!(sorted.next_switch() > now);
+        assert!(sorted.next_switch() <= now + INTERVAL);
+    }
+
+    #[test]
+    fn shuffled_rounds() {
+        let mut rng = Rng::new();
+        let mut shuffled = slideshow(false, 4, SlideshowOrder::Shuffle);
+        let shown = shown(&mut shuffled, &mut rng, 4 * 50);
+        // Each round shows every image once
+        for round in shown.chunks(4) {
+            let mut round = round.to_vec();
+            round.sort();
+            assert_eq!(round, [0, 1, 2, 3]);
+        }
+        // Not the same one twice in a row between the rounds
+        assert!(shown.windows(2).all(|pair| pair[0] != pair[1]), "{shown:?}");
+    }
+
+    #[test]
+    fn continue_from_previous() {
+        let mut rng = Rng::new();
+        let mut previous = slideshow(false, 5, SlideshowOrder::Sorted);
+        shown(&mut previous, &mut rng, 3);
+        let mut sorted = slideshow(false, 5, SlideshowOrder::Sorted);
+        sorted.continue_from(&previous);
+        assert_eq!(sorted.current(), 2);
+        // The current image is kept when the order changes
+        let mut shuffled = slideshow(false, 5, SlideshowOrder::Shuffle);
+        shuffled.continue_from(&previous);
+        assert_eq!(shuffled.current(), 2);
+        // Starting over with other images
+        let mut other = slideshow(false, 4, SlideshowOrder::Sorted);
+        other.continue_from(&previous);
+        assert_eq!(other.current(), 0);
+    }
+}
@@ -0,0 +1,274 @@
+#![allow(clippy::too_many_arguments)]
+
+use std::{
+    sync::atomic::{AtomicBool, Ordering::Relaxed},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+use smithay_client_toolkit::{
+    reexports::client::{
+        protocol::{
+            wl_buffer::WlBuffer,
+            wl_shm::{self, WlShm},
+        },
+        Proxy, QueueHandle,
+    },
+    registry::SimpleGlobal,
+    shm::raw::RawPool,
+};
+
+use crate::State;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransitionMode {
+    /// Switch wallpapers instantly
+    None,
+    /// Blend the previous wallpaper into the next one
+    Crossfade,
+    /// Slide the next wallpaper in from the direction of the workspace
+    Slide,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TransitionEasing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+}
+
+impl TransitionEasing {
+    // Cubic easing curves
+    fn apply(self, t: f32) -> f32 {
+        match self {
+            TransitionEasing::Linear => t,
+            TransitionEasing::EaseIn => t * t * t,
+            TransitionEasing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            TransitionEasing::EaseInOut => if t < 0.5 {
+                4.0 * t * t * t
+            } else {
+                1.0 - (2.0 - 2.0 * t).powi(3) / 2.0
+            },
+        }
+    }
+}
+
+/// Where the next wallpaper slides in from
+#[derive(Clone, Copy, Debug)]
+pub enum SlideFrom {
+    Left,
+    Right,
+    Top,
+    Bottom,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Effect {
+    Crossfade,
+    Slide(SlideFrom),
+}
+
+/// User data of the scratch buffers, tracking whether the compositor
+/// has released them so they can be drawn again
+pub struct ScratchBuffer {
+    released: AtomicBool,
+}
+
+impl ScratchBuffer {
+    pub fn release(&self) {
+        self.released.store(true, Relaxed);
+    }
+}
+
+/// Transition in progress on an output, rendering its frames into
+/// two scratch buffers in turns, freed once the transition is done
+pub struct Transition {
+    /// Pixels of the previous wallpaper
+    from: Vec<u8>,
+    pool: RawPool,
+    buffers: [WlBuffer; 2],
+    size: usize,
+    width: usize,
+    height: usize,
+    stride: usize,
+    format: wl_shm::Format,
+    effect: Effect,
+    easing: TransitionEasing,
+    last_rendered: Option<usize>,
+    start: Instant,
+    duration: Duration,
+    /// A frame is due but both scratch buffers are held by the compositor
+    pub waiting_for_release: bool,
+}
+
+impl Transition {
+    pub fn new(
+        from: Vec<u8>,
+        wl_shm: &WlShm,
+        qh: &QueueHandle<State>,
+        width: i32,
+        height: i32,
+        stride: i32,
+        format: wl_shm::Format,
+        effect: Effect,
+        easing: TransitionEasing,
+        duration: Duration,
+    ) -> anyhow::Result<Transition> {
+        let size = from.len();
+        let shm = SimpleGlobal::<WlShm, 1>::from_bound(wl_shm.clone());
+        let mut pool = RawPool::new(size * 2, &shm)
+            .context("Failed to create shm pool for the transition")?;
+        let buffers = [0, 1].map(|index| pool.create_buffer(
+            (index * size) as i32,
+            width,
+            height,
+            stride,
+            format,
+            ScratchBuffer { released: AtomicBool::new(true) },
+            qh,
+        ));
+        Ok(Transition {
+            from,
+            pool,
+            buffers,
+            size,
+            width: width as usize,
+            height: height as usize,
+            stride: stride as usize,
+            format,
+            effect,
+            easing,
+            last_rendered: None,
+            start: Instant::now(),
+            duration,
+            waiting_for_release: false,
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn format(&self) -> wl_shm::Format {
+        self.format
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.start.elapsed() >= self.duration
+    }
+
+    pub fn end(&self) -> Instant {
+        self.start + self.duration
+    }
+
+    /// Pixels of the last rendered frame, or of the previous wallpaper
+    /// if none was rendered yet, to continue from when interrupted
+    pub fn snapshot(mut self) -> Vec<u8> {
+        match self.last_rendered {
+            Some(index) => {
+                let offset = index * self.size;
+                self.pool.mmap()[offset..offset + self.size].to_vec()
+            },
+            None => std::mem::take(&mut self.from),
+        }
+    }
+
+    /// Render the next frame blending into the pixels of the next wallpaper,
+    /// returns the buffer to attach, or None if no scratch buffer is free
+    pub fn render(&mut self, to: &[u8]) -> Option<&WlBuffer> {
+        let index = (0..2).find(|&index| self.buffers[index]
+            .data::<ScratchBuffer>().unwrap()
+            .released.load(Relaxed)
+        )?;
+        let progress = self.start.elapsed().as_secs_f32()
+            / self.duration.as_secs_f32();
+        let progress = self.easing.apply(progress.clamp(0.0, 1.0));
+        let offset = index * self.size;
+        let dst = &mut self.pool.mmap()[offset..offset + self.size];
+        match self.effect {
+            Effect::Crossfade => {
+                // Fixed point weight of the next wallpaper in range [0, 256]
+                let weight = (progress * 256.0) as u32;
+                crossfade(dst, &self.from, to, weight);
+            },
+            Effect::Slide(slide_from) => {
+                let bytes_per_pixel = match self.format {
+                    wl_shm::Format::Bgr888 => 3,
+                    _ => 4,
+                };
+                // Sliding vertically is sliding a single row of all rows
+                let (rows, stride, row_len, unit) = match slide_from {
+                    SlideFrom::Left | SlideFrom::Right => (
+                        self.height,
+                        self.stride,
+                        self.width * bytes_per_pixel,
+                        bytes_per_pixel,
+                    ),
+                    SlideFrom::Top | SlideFrom::Bottom => {
+                        (1, self.size, self.size, self.stride)
+                    },
+                };
+                let shift = (progress * (row_len / unit) as f32) as usize
+                    * unit;
+                match slide_from {
+                    SlideFrom::Right | SlideFrom::Bottom => slide(
+                        dst, &self.from, to, rows, stride, row_len,
+                        shift, row_len - shift,
+                    ),
+                    SlideFrom::Left | SlideFrom::Top => slide(
+                        dst, to, &self.from, rows, stride, row_len,
+                        row_len - shift, shift,
+                    ),
+                }
+            },
+        }
+        self.buffers[index].data::<ScratchBuffer>().unwrap()
+            .released.store(false, Relaxed);
+        self.last_rendered = Some(index);
+        Some(&self.buffers[index])
+    }
+}
+
+impl Drop for Transition {
+    fn drop(&mut self) {
+        for buffer in &self.buffers {
+            buffer.destroy();
+        }
+    }
+}
+
+// Blend every byte alike, which works for any of our 8 bit per channel
+// pixel formats
+fn crossfade(dst: &mut [u8], from: &[u8], to: &[u8], weight: u32) {
+    for ((dst, &from), &to) in dst.iter_mut().zip(from).zip(to) {
+        *dst = ((from as u32 * (256 - weight) + to as u32 * weight) >> 8) as u8;
+    }
+}
+
+// Compose each row of the first pixels starting at the offset,
+// followed by the second pixels from the beginning of the row
+fn slide(
+    dst: &mut [u8],
+    first: &[u8],
+    second: &[u8],
+    rows: usize,
+    stride: usize,
+    row_len: usize,
+    first_offset: usize,
+    first_len: usize,
+) {
+    for row in 0..rows {
+        let start = row * stride;
+        let (dst_first, dst_second) =
+            dst[start..start + row_len].split_at_mut(first_len);
+        let first_start = start + first_offset;
+        dst_first.copy_from_slice(&first[first_start..first_start + first_len]);
+        dst_second.copy_from_slice(
+            &second[start..start + row_len - first_len]
+        );
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    io,
+    mem::MaybeUninit,
+    os::{
+        fd::{AsFd, OwnedFd},
+        unix::ffi::OsStrExt,
+    },
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use log::{debug, error, warn};
+use rustix::{
+    fs::inotify::{
+        self, CreateFlags, ReadFlags, Reader, WatchFlags, add_watch,
+        remove_watch,
+    },
+    io::Errno,
+};
+
+// Watch directories instead of files, so atomic replaces by rename
+// and changes of symlinks are noticed as well
+const WATCH_FLAGS: WatchFlags = WatchFlags::ATTRIB
+    .union(WatchFlags::CLOSE_WRITE)
+    .union(WatchFlags::CREATE)
+    .union(WatchFlags::DELETE)
+    .union(WatchFlags::DELETE_SELF)
+    .union(WatchFlags::MOVE_SELF)
+    .union(WatchFlags::MOVED_FROM)
+    .union(WatchFlags::MOVED_TO)
+    .union(WatchFlags::ONLYDIR);
+
+pub struct DirWatcher {
+    fd: Rc<OwnedFd>,
+    watches: HashMap<i32, PathBuf>,
+}
+
+impl DirWatcher {
+    pub fn new() -> io::Result<DirWatcher> {
+        let fd = inotify::init(CreateFlags::CLOEXEC | CreateFlags::NONBLOCK)?;
+        Ok(DirWatcher { fd: Rc::new(fd), watches: HashMap::new() })
+    }
+
+    pub fn fd(&self) -> Rc<OwnedFd> {
+        Rc::clone(&self.fd)
+    }
+
+    /// Set the complete list of watched directories,
+    /// adding the new ones and removing the ones no longer listed
+    pub fn watch_dirs<'a>(
+        &mut self,
+        dirs: impl IntoIterator<Item = &'a Path>,
+    ) {
+        let mut wanted: Vec<&Path> = dirs.into_iter().collect();
+        wanted.sort_unstable();
+        wanted.dedup();
+        let stale = self.watches.iter()
+            .filter(|(_, dir)| !wanted.contains(&dir.as_path()))
+            .map(|(wd, _)| *wd)
+            .collect::<Vec<_>>();
+        for wd in stale {
+            let dir = self.watches.remove(&wd).unwrap();
+            debug!("Stop watching directory {dir:?}");
+            // Fails with EINVAL if the kernel already removed the watch
+            // because the directory was deleted, that is fine
+            if let Err(e) = remove_watch(self.fd.as_fd(), wd) {
+                if e != Errno::INVAL {
+                    error!("Failed to stop watching directory {dir:?}: {e}");
+                }
+            }
+        }
+        for dir in wanted {
+            if self.watches.values().any(|watched| watched == dir) {
+                continue
+            }
+            match add_watch(self.fd.as_fd(), dir, WATCH_FLAGS) {
+                Ok(wd) => {
+                    debug!("Watching directory {dir:?}");
+                    self.watches.insert(wd, dir.to_path_buf());
+                },
+                Err(e) => error!("Failed to watch directory {dir:?}: {e}"),
+            }
+        }
+    }
+
+    /// Drain the pending events and return the watched directories
+    /// which had changes relevant for wallpapers
+    pub fn read(&mut self) -> io::Result<Vec<PathBuf>> {
+        let mut buf = [MaybeUninit::<u8>::uninit(); 4096];
+        let mut reader = Reader::new(self.fd.as_fd(), &mut buf);
+        let mut changed_dirs = Vec::new();
+        loop {
+            let event = match reader.next() {
+                Ok(event) => event,
+                #[allow(unreachable_patterns)]
+                Err(Errno::AGAIN) | Err(Errno::WOULDBLOCK) => break,
+                Err(e) => return Err(e.into()),
+            };
+            let flags = event.events();
+            if flags.contains(ReadFlags::QUEUE_OVERFLOW) {
+                warn!("Directory watch event queue overflowed, \
+                    assuming changes in all watched directories");
+                changed_dirs.extend(self.watches.values().cloned());
+                continue
+            }
+            let Some(dir) = self.watches.get(&event.wd()) else {
+                continue
+            };
+            if flags.contains(ReadFlags::IGNORED) {
+                debug!("Directory {dir:?} is no longer watched");
+                changed_dirs.push(dir.clone());
+                self.watches.remove(&event.wd());
+                continue
+            }
+            let name = event.file_name()
+                .map(|name| OsStr::from_bytes(name.to_bytes()));
+            // A newly created regular file is probably still being written,
+            // wait for its CLOSE_WRITE instead of loading it half-written
+            if flags.contains(ReadFlags::CREATE)
+                && !flags.contains(ReadFlags::ISDIR)
+            {
+                if let Some(name) = name {
+                    let is_symlink = dir.join(name).symlink_metadata()
+                        .is_ok_and(|metadata| metadata.is_symlink());
+                    if !is_symlink {
+                        continue
+                    }
+                }
+            }
+            debug!("Directory watch event {flags:?} in {dir:?} for {name:?}");
+            if !changed_dirs.contains(dir) {
+                changed_dirs.push(dir.clone());
+            }
+        }
+        Ok(changed_dirs)
+    }
+}
@@ -1,20 +1,29 @@
+#![allow(clippy::too_many_arguments)]
+
 use std::{
     cell::RefCell,
+    cmp::Ordering,
+    fmt::{self, Write as _},
     os::fd::AsFd,
     path::PathBuf,
     rc::{Rc, Weak},
+    sync::Arc,
+    time::{Duration, Instant},
 };
 
 use anyhow::{bail, Context};
-use log::{debug, error, warn};
+use image::DynamicImage;
+use log::{debug, error, info, warn};
 use rustix::fs::{Dev, major, minor};
+use serde_json::json;
 use smithay_client_toolkit::{
     delegate_compositor, delegate_dmabuf, delegate_layer, delegate_output,
     delegate_registry, delegate_shm,
     compositor::{CompositorHandler, Region},
     dmabuf::{DmabufFeedback, DmabufHandler, DmabufState},
     output::{OutputHandler, OutputState},
-    registry::{ProvidesRegistryState, RegistryState},
+    globals::ProvidesBoundGlobal,
+    registry::{ProvidesRegistryState, RegistryState, SimpleGlobal},
     registry_handlers,
     shell::{
         WaylandSurface,
@@ -31,9 +40,9 @@ use smithay_client_toolkit::{
 use smithay_client_toolkit::reexports::client::{
     Connection, Dispatch, Proxy, QueueHandle,
     protocol::{
-        wl_buffer::WlBuffer,
+        wl_buffer::{self, WlBuffer},
         wl_output::{self, Transform, WlOutput},
-        wl_shm,
+        wl_shm::{self, WlShm},
         wl_surface::WlSurface,
     },
 };
@@ -50,15 +59,40 @@ use smithay_client_toolkit::reexports::protocols::wp::{
 
 use crate::{
     flush_blocking, State,
+    animation::{Animation, Playback},
+    config::OutputOptions,
     gpu::{
         DRM_FORMAT_XRGB8888, fmt_modifier,
         GpuMemory, GpuUploader, GpuWallpaper,
     },
-    image::{load_wallpaper, output_wallpaper_files, WallpaperFile},
+    events::{Event, Subscribers},
+    export::Exporter,
+    hook::ChangeHook,
+    image::{
+        ImageFrames, Processing, draw_decoder, draw_image, find_output_dir,
+        frame_delay, load_wallpaper, open_image_frames,
+        output_wallpaper_files, wallpaper_file, WallpaperFile,
+    },
+    pattern::NamePattern,
+    persist::PersistentOverride,
+    random::RandomPool,
+    schedule::TimeOfDay,
+    slideshow::Slideshow,
+    transition::{
+        Effect, ScratchBuffer, SlideFrom, Transition, TransitionMode,
+    },
 };
 
 const MAX_FDS_OUT: usize = 28;
 
+/// A wallpaper switch for the on-change hook, the export
+/// and the subscribers
+struct Switch {
+    workspace_name: String,
+    workspace_number: i32,
+    path: PathBuf,
+}
+
 pub struct BackgroundLayer {
     pub output_name: String,
     output_make_model_serial: String,
@@ -70,8 +104,49 @@ pub struct BackgroundLayer {
     current_wallpaper: Option<Rc<RefCell<Wallpaper>>>,
     queued_wallpaper: Option<Weak<RefCell<Wallpaper>>>,
     transform: Transform,
+    scaling: Scaling,
     viewport: Option<WpViewport>,
     dmabuf_feedback: Option<ZwpLinuxDmabufFeedbackV1>,
+    gpu_target: Option<GpuTarget>,
+    visible_workspace: Option<(String, i32)>,
+    /// The visible workspace has windows
+    occupied: bool,
+    /// Number of the workspace whose wallpaper was last set
+    shown_workspace_number: Option<i32>,
+    /// Slide transitions go up and down instead of left and right
+    vertical_workspaces: bool,
+    /// Which variants of the wallpapers to show
+    time_of_day: TimeOfDay,
+    wallpaper_dirs: Vec<PathBuf>,
+    options: OutputOptions,
+    slideshows: Vec<Slideshow>,
+    overrides: Vec<Override>,
+    failed_files: Vec<(PathBuf, String)>,
+    random_pool: RandomPool<Rc<RefCell<Wallpaper>>>,
+    /// Pixel format of the wallpapers in shm, also for loading
+    /// the images of the random pool when picked
+    shm_format: wl_shm::Format,
+    /// Load the images of the random pool to GPU memory
+    /// same as the other wallpapers
+    random_gpu: bool,
+    /// Image of the random pool picked to be shown but waiting
+    /// to be loaded to GPU memory by load_picked_random_wallpapers
+    random_gpu_pending: Option<usize>,
+    on_change: Option<Rc<ChangeHook>>,
+    subscribers: Arc<Subscribers>,
+    exporter: Option<Rc<Exporter>>,
+    transition: Option<Transition>,
+    /// Switch to announce once the transition in progress finishes
+    pending_switch: Option<Switch>,
+    /// Playing the animation of the current wallpaper
+    playback: Option<Playback>,
+    /// A frame callback was requested for the transition
+    /// or the animation in progress
+    frame_pending: bool,
+    wl_shm: WlShm,
+    qh: QueueHandle<State>,
+    /// Committed a wallpaper or found none to show
+    settled: bool,
 }
 
 impl Drop for BackgroundLayer {
@@ -90,20 +165,56 @@ impl BackgroundLayer {
         &mut self,
         workspace_name: &str,
         workspace_number: i32,
+        occupied: bool,
     ) {
+        let newly_visible = self.visible_workspace.as_ref()
+            .is_none_or(|(name, number)| name != workspace_name
+                || *number != workspace_number
+            );
+        self.visible_workspace = Some((workspace_name.to_string(),
+            workspace_number));
+        self.occupied = occupied;
+
         if !self.configured {
             error!("Cannot draw wallpaper image on the not yet configured \
                 layer for output: {}", self.output_name);
             return
         }
 
-        let Some(workspace_bg) = self.workspace_backgrounds.iter()
-            .find(|bg| bg.workspace_name == workspace_name)
-            .or_else(|| self.workspace_backgrounds.iter()
-                .find(|bg| bg.workspace_number == workspace_number)
-            )
-            .or_else(|| self.workspace_backgrounds.iter()
+        let random_bg;
+        let own_bg = own_background(
+            &self.workspace_backgrounds,
+            self.time_of_day,
+            occupied,
+            workspace_name,
+            workspace_number,
+        );
+        // Workspaces without their own wallpaper get one from
+        // the random pool if there is any, before the default
+        let chosen_bg = match own_bg {
+            Some(own_bg) => Some(own_bg),
+            None => {
+                random_bg = self.random_background(
+                    workspace_name,
+                    newly_visible,
+                );
+                if self.random_gpu_pending.is_some() {
+                    debug!("Wallpaper for output {} workspace {} \
+                        is not loaded yet", self.output_name, workspace_name);
+                    return
+                }
+                random_bg.as_ref().map(|bg| (bg, "random pool"))
+            },
+        };
+        let files = || current_files(
+            &self.workspace_backgrounds,
+            self.time_of_day,
+            occupied,
+        );
+        let Some((mut workspace_bg, rule)) = chosen_bg
+            .or_else(|| files()
                 .find(|bg| bg.workspace_name == "_default")
+                .map(|bg| (bg, "default"))
             )
         else {
             error!(
@@ -115,8 +226,31 @@ impl BackgroundLayer {
                     .map(|workspace_bg| workspace_bg.workspace_name.as_str())
                     .collect::<Vec<_>>().join(", ")
             );
+            self.settled = true;
             return
         };
+        debug!("Wallpaper {} chosen by {} for output {} workspace {}",
+            workspace_bg.workspace_name, rule, self.output_name,
+            workspace_name);
+        // Workspaces with a slideshow have a background for each image
+        if let Some(slideshow) = self.slideshows.iter().find(|slideshow|
+            workspace_bg.override_scope.is_none() && slideshow.shows(
+                &workspace_bg.workspace_name,
+                workspace_bg.variant,
+                workspace_bg.occupied,
+            )
+        ) {
+            if let Some(bg) = files()
+                .filter(|bg| slideshow.shows(
+                    &bg.workspace_name,
+                    bg.variant,
+                    bg.occupied,
+                ))
+                .nth(slideshow.current())
+            {
+                workspace_bg = bg;
+            }
+        }
         let wallpaper = &workspace_bg.wallpaper;
 
         if let Some(current) = &self.current_wallpaper {
@@ -136,35 +270,513 @@ impl BackgroundLayer {
             return
         };
 
-        // Attach and commit to new workspace background
-        self.layer.attach(Some(wl_buffer), 0, 0);
-        // wallpaper_borrow.active_count += 1;
+        // A new switch interrupts the transition in progress
+        let interrupted = self.transition.take();
+        let transition = self.new_transition(
+            interrupted,
+            &wallpaper_borrow,
+            workspace_number,
+        );
+        let is_transition = transition.is_some();
+        self.transition = transition;
 
-        // Damage the entire surface
-        self.layer.wl_surface().damage_buffer(0, 0, self.width, self.height);
+        if !is_transition {
+            // Attach and commit to new workspace background
+            self.layer.attach(Some(wl_buffer), 0, 0);
+            // wallpaper_borrow.active_count += 1;
 
-        self.layer.commit();
+            // Damage the entire surface
+            self.layer.wl_surface()
+                .damage_buffer(0, 0, self.width, self.height);
+
+            self.layer.commit();
+        }
 
         self.current_wallpaper = Some(Rc::clone(wallpaper));
         self.queued_wallpaper = None;
+        self.shown_workspace_number = Some(workspace_number);
+        self.playback = wallpaper_borrow.animation.as_ref()
+            .map(Playback::start);
+        self.settled = true;
 
         debug!("Setting wallpaper on output {} for workspace: {}",
             self.output_name, workspace_name);
+
+        let switch = Switch {
+            workspace_name: workspace_name.to_string(),
+            workspace_number,
+            path: workspace_bg.path.clone(),
+        };
+        drop(wallpaper_borrow);
+        if is_transition {
+            self.pending_switch = Some(switch);
+            if !self.frame_pending {
+                self.step_transition();
+            }
+        } else {
+            self.pending_switch = None;
+            self.announce_switch(&switch);
+        }
     }
+
+    // Run the on-change hook, export the wallpaper and notify
+    // the subscribers, once the switch to it is shown
+    fn announce_switch(&self, switch: &Switch) {
+        let Some(wallpaper) = self.current_wallpaper.as_ref() else { return };
+        let wallpaper = wallpaper.borrow();
+        if let Some(on_change) = &self.on_change {
+            on_change.run(
+                &self.output_name,
+                &switch.workspace_name,
+                switch.workspace_number,
+                &switch.path,
+                &wallpaper.canon_path,
+            );
+        }
+        if let Some(exporter) = &self.exporter {
+            exporter.export(
+                &self.output_name,
+                &wallpaper.canon_path,
+                self.width,
+                self.height,
+                wallpaper.processing,
+            );
+        }
+        self.subscribers.emit(Event::WallpaperSwitched {
+            output: &self.output_name,
+            workspace: &switch.workspace_name,
+            workspace_number: switch.workspace_number,
+            path: &switch.path,
+            canonical_path: &wallpaper.canon_path,
+        });
+    }
+
+    // Pick an image from the random pool, loading it if not cached,
+    // and dropping the ones failing to load from the pool
+    fn random_background(
+        &mut self,
+        workspace_name: &str,
+        newly_visible: bool,
+    ) -> Option<WorkspaceBackground> {
+        loop {
+            let index = self.random_pool.pick(workspace_name, newly_visible)?;
+            let wallpaper = match self.random_pool.cached(index) {
+                Some(wallpaper) => wallpaper,
+                // Loading to GPU memory needs the state
+                None if self.random_gpu => {
+                    self.random_gpu_pending = Some(index);
+                    return None
+                },
+                None => match self.load_random(index, None) {
+                    Ok(wallpaper) => {
+                        self.random_pool.insert(index, Rc::clone(&wallpaper));
+                        wallpaper
+                    },
+                    Err(e) => {
+                        self.drop_random(index, e);
+                        continue
+                    },
+                },
+            };
+            let file = self.random_pool.file(index);
+            return Some(WorkspaceBackground {
+                workspace_name: file.workspace.clone(),
+                workspace_number: file.workspace_number,
+                pattern: None,
+                override_scope: None,
+                variant: None,
+                occupied: false,
+                path: file.path.clone(),
+                wallpaper,
+            })
+        }
+    }
+
+    fn drop_random(&mut self, index: usize, e: anyhow::Error) {
+        let file = self.random_pool.remove(index);
+        error!("Failed to load wallpaper: {e:#}");
+        let error = format!("{e:#}");
+        self.subscribers.emit(Event::WallpaperFailed {
+            output: &self.output_name,
+            path: &file.path,
+            error: &error,
+        });
+        self.failed_files.push((file.path, error));
+    }
+
+    // Load to GPU memory if given the GPU uploader, otherwise to shm
+    fn load_random(
+        &self,
+        index: usize,
+        gpu: Option<(&mut GpuUploader, &DmabufState)>,
+    ) -> anyhow::Result<Rc<RefCell<Wallpaper>>> {
+        let wallpaper_file = self.random_pool.file(index);
+        let processing = file_processing(&self.options, wallpaper_file);
+        let gpu_uploader = gpu.as_ref().map(|(uploader, _)| &**uploader);
+        // Such as the shown one after reloading the wallpapers
+        if let Some(current) = self.current_wallpaper.as_ref()
+            .filter(|current| current.borrow().is_loaded_from(
+                wallpaper_file,
+                gpu_uploader,
+                self.shm_format,
+                processing,
+            ))
+        {
+            return Ok(Rc::clone(current))
+        }
+        if let Some(wallpaper) = find_equal_output_wallpaper(
+            &self.workspace_backgrounds,
+            wallpaper_file,
+            gpu_uploader,
+            self.shm_format,
+            processing,
+        ) {
+            return Ok(wallpaper)
+        }
+        debug!("Loading wallpaper {:?} from the random pool of output {}",
+            wallpaper_file.path, self.output_name);
+        let mut resizer = fast_image_resize::Resizer::new();
+        if let Some((uploader, dmabuf_state)) = gpu {
+            load_wallpaper(
+                &wallpaper_file.path,
+                uploader.staging_buffer(),
+                self.width as u32,
+                self.height as u32,
+                self.width as usize * 4,
+                wl_shm::Format::Xrgb8888,
+                processing,
+                &mut resizer,
+            )?;
+            match uploader.upload() {
+                Ok(gpu_wallpaper) => return Ok(wallpaper_dmabuf(
+                    dmabuf_state,
+                    &self.qh,
+                    gpu_wallpaper,
+                    self.width,
+                    self.height,
+                    wallpaper_file.canon_path.clone(),
+                    wallpaper_file.canon_modified,
+                    processing,
+                )),
+                Err(e) => error!("Failed to upload wallpaper to GPU, \
+                    falling back to shm: {e:#}"),
+            }
+        }
+        let shm = SimpleGlobal::<WlShm, 1>::from_bound(self.wl_shm.clone());
+        let wallpaper = load_shm_wallpaper(
+            wallpaper_file,
+            &shm,
+            &self.qh,
+            self.width,
+            self.height,
+            self.shm_format,
+            processing,
+            &self.options,
+            &mut resizer,
+        )?;
+        malloc_trim_all();
+        Ok(wallpaper)
+    }
+
+    // Start a transition from the wallpaper shown now to the next one
+    // if enabled and both are in CPU memory
+    fn new_transition(
+        &self,
+        interrupted: Option<Transition>,
+        next: &Wallpaper,
+        workspace_number: i32,
+    ) -> Option<Transition> {
+        let effect = match self.options.transition {
+            TransitionMode::None => return None,
+            TransitionMode::Crossfade => Effect::Crossfade,
+            TransitionMode::Slide => {
+                let previous = self.shown_workspace_number?;
+                let vertical = self.vertical_workspaces;
+                match workspace_number.cmp(&previous) {
+                    Ordering::Greater if vertical => {
+                        Effect::Slide(SlideFrom::Bottom)
+                    },
+                    Ordering::Greater => Effect::Slide(SlideFrom::Right),
+                    Ordering::Less if vertical => Effect::Slide(SlideFrom::Top),
+                    Ordering::Less => Effect::Slide(SlideFrom::Left),
+                    Ordering::Equal => return None,
+                }
+            },
+        };
+        let Memory::WlShm { format, stride, .. } = next.memory else {
+            return None
+        };
+        let size = stride * self.height as usize;
+        let from = match interrupted {
+            Some(interrupted) => {
+                if interrupted.format() != format
+                    || interrupted.size() != size
+                {
+                    return None
+                }
+                interrupted.snapshot()
+            },
+            None => {
+                let mut current = self.current_wallpaper.as_ref()?
+                    .borrow_mut();
+                // The frame shown if the current wallpaper is animated
+                let offset = self.playback.as_ref()
+                    .map_or(0, |playback| playback.frame * size);
+                match &mut current.memory {
+                    Memory::WlShm { pool, format: current_format, .. }
+                        if *current_format == format
+                            && pool.len() >= offset + size =>
+                    {
+                        pool.mmap()[offset..offset + size].to_vec()
+                    },
+                    _ => return None,
+                }
+            },
+        };
+        match Transition::new(
+            from,
+            &self.wl_shm,
+            &self.qh,
+            self.width,
+            self.height,
+            stride as i32,
+            format,
+            effect,
+            self.options.transition_easing,
+            self.options.transition_duration,
+        ) {
+            Ok(transition) => {
+                debug!("Starting transition on output {}", self.output_name);
+                Some(transition)
+            },
+            Err(e) => {
+                error!("{e:#}");
+                None
+            },
+        }
+    }
+
+    // Show the next frame of the transition in progress,
+    // or the wallpaper itself once it is done
+    fn step_transition(&mut self) {
+        if self.transition.as_ref().is_some_and(Transition::is_done) {
+            self.finish_transition();
+            return
+        }
+        let Some(transition) = self.transition.as_mut() else { return };
+        let Some(wallpaper) = self.current_wallpaper.clone() else { return };
+        let mut wallpaper = wallpaper.borrow_mut();
+        let surface = self.layer.wl_surface();
+        let Memory::WlShm { pool, .. } = &mut wallpaper.memory else {
+            unreachable!()
+        };
+        let next = &pool.mmap()[..transition.size()];
+        let Some(buffer) = transition.render(next) else {
+            transition.waiting_for_release = true;
+            return
+        };
+        self.layer.attach(Some(buffer), 0, 0);
+        surface.damage_buffer(0, 0, self.width, self.height);
+        surface.frame(&self.qh, surface.clone());
+        self.frame_pending = true;
+        self.layer.commit();
+    }
+
+    // Attach the wallpaper itself and free the transition, also when
+    // its end is reached without frame callbacks, such as on outputs
+    // which are hidden or turned off
+    fn finish_transition(&mut self) {
+        debug!("Transition finished on output {}", self.output_name);
+        self.transition = None;
+        let Some(wallpaper) = self.current_wallpaper.clone() else { return };
+        let wallpaper = wallpaper.borrow();
+        self.playback = wallpaper.animation.as_ref().map(Playback::start);
+        self.layer.attach(wallpaper.wl_buffer.as_ref(), 0, 0);
+        self.layer.wl_surface()
+            .damage_buffer(0, 0, self.width, self.height);
+        self.layer.commit();
+        drop(wallpaper);
+        if let Some(switch) = self.pending_switch.take() {
+            self.announce_switch(&switch);
+        }
+    }
+
+    /// When the next frame of the animation or the end of the transition
+    /// is due, None if there is nothing to do until a frame callback
+    pub fn next_animation_frame(&self) -> Option<Instant> {
+        if let Some(transition) = &self.transition {
+            return Some(transition.end())
+        }
+        if self.frame_pending {
+            return None
+        }
+        self.playback.as_ref().map(|playback| playback.next_frame)
+    }
+
+    // Show the next frame of the animation if it is due,
+    // throttled by frame callbacks so it stops while the output is hidden
+    fn step_animation(&mut self, now: Instant) {
+        if self.next_animation_frame().is_none_or(|next| next > now) {
+            return
+        }
+        if self.transition.is_some() {
+            self.finish_transition();
+            return
+        }
+        let Some(wallpaper) = self.current_wallpaper.clone() else { return };
+        let wallpaper = wallpaper.borrow();
+        let Some(animation) = wallpaper.animation.as_ref() else { return };
+        let playback = self.playback.as_mut().unwrap();
+        playback.advance(animation, now);
+        let buffer = match playback.frame {
+            0 => wallpaper.wl_buffer.as_ref(),
+            frame => Some(animation.buffer(frame)),
+        };
+        let surface = self.layer.wl_surface();
+        self.layer.attach(buffer, 0, 0);
+        surface.damage_buffer(0, 0, self.width, self.height);
+        surface.frame(&self.qh, surface.clone());
+        self.frame_pending = true;
+        self.layer.commit();
+    }
+
+    fn has_variants(&self) -> bool {
+        self.workspace_backgrounds.iter().any(|bg| bg.variant.is_some())
+    }
+
+    pub fn redraw(&mut self) {
+        if let Some((name, number)) = self.visible_workspace.clone() {
+            self.draw_workspace_bg(&name, number, self.occupied);
+        }
+    }
+
+    /// Remember the visible workspace without drawing its wallpaper
+    pub fn set_visible_workspace(
+        &mut self,
+        workspace_name: &str,
+        workspace_number: i32,
+        occupied: bool,
+    ) {
+        self.visible_workspace = Some((workspace_name.to_string(),
+            workspace_number));
+        self.occupied = occupied;
+    }
+
+    pub fn has_wallpaper(&self) -> bool {
+        self.current_wallpaper.is_some()
+    }
+
+    pub fn is_settled(&self) -> bool {
+        self.settled
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Scaling {
+    None,
+    Integer(i32),
+    Viewport,
+}
+
+impl fmt::Display for Scaling {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scaling::None => write!(f, "none"),
+            Scaling::Integer(factor) => write!(f, "integer {factor}"),
+            Scaling::Viewport => write!(f, "viewport"),
+        }
+    }
+}
+
+// The DMA-BUF feedback the wallpapers of an output were uploaded for,
+// needed to create GPU uploaders again when reloading them
+struct GpuTarget {
+    dmabuf_drm_dev: Option<Dev>,
+    drm_format_modifiers: Vec<u64>,
+}
+
+/// Wallpaper set at runtime, shown instead of the wallpapers from files
+#[derive(Clone)]
+struct Override {
+    /// None for all workspaces of the output
+    workspace: Option<String>,
+    path: PathBuf,
+    /// Saved to be restored after restarts
+    persistent: bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OverrideScope {
+    Workspace,
+    Output,
 }
 
 struct WorkspaceBackground {
     workspace_name: String,
     workspace_number: i32,
+    pattern: Option<NamePattern>,
+    override_scope: Option<OverrideScope>,
+    variant: Option<TimeOfDay>,
+    /// Shown only while the workspace has windows
+    occupied: bool,
+    /// Path of the wallpaper file, not resolving symlinks
+    path: PathBuf,
     wallpaper: Rc<RefCell<Wallpaper>>,
 }
 
+impl WorkspaceBackground {
+    // Whether this is the variant to show now among the variants of
+    // its workspace: the occupied ones if the workspace has windows and
+    // there is any, then the closest to the time of day among those
+    fn is_current_variant(
+        &self,
+        workspace_backgrounds: &[WorkspaceBackground],
+        time_of_day: TimeOfDay,
+        occupied: bool,
+    ) -> bool {
+        let variants = || workspace_backgrounds.iter()
+            .filter(|bg| bg.override_scope.is_none()
+                && bg.workspace_name == self.workspace_name
+            );
+        let occupied = if variants().any(|bg| bg.occupied == occupied) {
+            occupied
+        } else {
+            !occupied
+        };
+        if self.occupied != occupied {
+            return false
+        }
+        time_of_day.preference().into_iter()
+            .find(|&variant| variants()
+                .any(|bg| bg.occupied == occupied && bg.variant == variant)
+            )
+            == Some(self.variant)
+    }
+}
+
 struct Wallpaper {
     wl_buffer: Option<WlBuffer>,
     // active_count: usize,
     memory: Memory,
     canon_path: PathBuf,
     canon_modified: u128,
+    processing: Processing,
+    animation: Option<Animation>,
+}
+
+impl Wallpaper {
+    fn is_loaded_from(
+        &self,
+        wallpaper_file: &WallpaperFile,
+        gpu_uploader: Option<&GpuUploader>,
+        shm_format: wl_shm::Format,
+        processing: Processing,
+    ) -> bool {
+        self.canon_modified == wallpaper_file.canon_modified
+            && self.canon_path == wallpaper_file.canon_path
+            && self.processing == processing
+            && self.memory.target_eq(gpu_uploader, shm_format)
+    }
 }
 
 impl Drop for Wallpaper {
@@ -180,12 +792,16 @@ impl Drop for Wallpaper {
 }
 
 enum Memory {
-    WlShm { pool: RawPool },
+    WlShm { pool: RawPool, format: wl_shm::Format, stride: usize },
     Dmabuf { gpu_memory: GpuMemory, params: Option<ZwpLinuxBufferParamsV1> },
 }
 
 impl Memory {
-    fn gpu_uploader_eq(&self, gpu_uploader: Option<&GpuUploader>) -> bool {
+    fn target_eq(
+        &self,
+        gpu_uploader: Option<&GpuUploader>,
+        shm_format: wl_shm::Format,
+    ) -> bool {
         if let Some(gpu_uploader) = gpu_uploader {
             match self {
                 Memory::WlShm { .. } => false,
@@ -195,7 +811,7 @@ impl Memory {
             }
         } else {
             match self {
-                Memory::WlShm { .. } => true,
+                Memory::WlShm { format, .. } => *format == shm_format,
                 Memory::Dmabuf { .. } => false,
             }
         }
@@ -232,9 +848,16 @@ impl CompositorHandler for State {
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
-        _surface: &WlSurface,
+        surface: &WlSurface,
         _time: u32,
     ) {
+        if let Some(bg_layer) = self.background_layers.iter_mut()
+            .find(|bg_layer| bg_layer.layer.wl_surface() == surface)
+        {
+            bg_layer.frame_pending = false;
+            bg_layer.step_transition();
+            bg_layer.step_animation(Instant::now());
+        }
     }
 
     fn transform_changed(
@@ -319,13 +942,38 @@ impl DmabufHandler for State {
                             if Rc::ptr_eq(&queued, wallpaper) {
                                 let name = workspace_bg.workspace_name.clone();
                                 let number = workspace_bg.workspace_number;
-                                bg_layer.draw_workspace_bg(&name, number);
+                                let occupied = bg_layer.occupied;
+                                bg_layer.draw_workspace_bg(
+                                    &name,
+                                    number,
+                                    occupied,
+                                );
                             }
                         }
                     }
                     return
                 }
             }
+            let Some(wallpaper) = bg_layer.random_pool.cached_images()
+                .find(|wallpaper| wallpaper.borrow_mut().memory
+                    .dmabuf_params_destroy_eq(params)
+                )
+                .cloned()
+            else {
+                continue
+            };
+            let mut wallpaper_borrow = wallpaper.borrow_mut();
+            wallpaper_borrow.wl_buffer = Some(buffer);
+            debug!("Created Linux DMA-BUF buffer for wallpaper \
+                file {:?}", wallpaper_borrow.canon_path);
+            drop(wallpaper_borrow);
+            if bg_layer.queued_wallpaper.as_ref()
+                .and_then(Weak::upgrade)
+                .is_some_and(|queued| Rc::ptr_eq(&queued, &wallpaper))
+            {
+                bg_layer.redraw();
+            }
+            return
         }
         error!("Received unexpected created Linux DMA-BUF buffer");
     }
@@ -339,8 +987,11 @@ impl DmabufHandler for State {
         error!("Failed to create a Linux DMA-BUF buffer");
         let mut failed_bg_layer_indecies = Vec::new();
         for (i, bg_layer) in self.background_layers.iter_mut().enumerate() {
-            for workspace_bg in bg_layer.workspace_backgrounds.iter_mut() {
-                let mut wallpaper = workspace_bg.wallpaper.borrow_mut();
+            for wallpaper in bg_layer.workspace_backgrounds.iter()
+                .map(|workspace_bg| &workspace_bg.wallpaper)
+                .chain(bg_layer.random_pool.cached_images())
+            {
+                let mut wallpaper = wallpaper.borrow_mut();
                 if wallpaper.memory.dmabuf_params_destroy_eq(params) {
                     error!("Falling back to shm and reloading wallpapers \
                         for output {}", bg_layer.output_name);
@@ -442,6 +1093,10 @@ impl OutputHandler for State {
 
         let output_make_model_serial = self.compositor_connection_task
             .request_make_model_serial(&output_name);
+        let options = self.config.output_options(
+            &output_name,
+            &output_make_model_serial,
+        );
 
         let Some((width, height)) = info.modes.iter()
             .find(|mode| mode.current)
@@ -511,6 +1166,11 @@ impl OutputHandler for State {
             factor: {}, logical size: {}x{}, transform: {:?}",
             output_name, width, height, integer_scale_factor,
             logical_width, logical_height, info.transform);
+        self.subscribers.emit(Event::OutputAdded {
+            output: &output_name,
+            width,
+            height,
+        });
         if self.show_serials {
             debug!("New output make-model-serial: '{}'",
                 output_make_model_serial);
@@ -545,26 +1205,31 @@ impl OutputHandler for State {
         };
 
         let mut viewport = None;
+        let scaling;
 
         if width == logical_width || height == logical_height {
             debug!("Output {} needs no scaling", output_name);
+            scaling = Scaling::None;
         } else if width == logical_width * integer_scale_factor
             && height == logical_height * integer_scale_factor
         {
             debug!("Output {} needs integer scaling", output_name);
             surface.set_buffer_scale(integer_scale_factor);
+            scaling = Scaling::Integer(integer_scale_factor);
         } else {
             debug!("Output {} needs fractional scaling", output_name);
             let new_viewport = self.viewporter.get_viewport(surface, qh, ());
             new_viewport.set_destination(logical_width, logical_height);
             viewport = Some(new_viewport);
+            scaling = Scaling::Viewport;
         }
 
         layer.commit();
 
         let mut dmabuf_feedback = None;
         let mut gpu_uploader = None;
-        if let Some(gpu) = self.gpu.as_mut() {
+        let mut gpu_target = None;
+        if let Some(gpu) = self.gpu.as_mut().filter(|_| options.gpu) {
             if self.dmabuf_state.version().unwrap() >= 4 {
                 match self.dmabuf_state.get_surface_feedback(surface, qh) {
                     Ok(feedback) => {
@@ -589,14 +1254,32 @@ impl OutputHandler for State {
                     None,
                     width as u32,
                     height as u32,
-                    drm_format_modifiers,
+                    drm_format_modifiers.clone(),
                 ) {
-                    Ok(uploader) => gpu_uploader = Some(uploader),
+                    Ok(uploader) => {
+                        gpu_uploader = Some(uploader);
+                        gpu_target = Some(GpuTarget {
+                            dmabuf_drm_dev: None,
+                            drm_format_modifiers,
+                        });
+                    },
                     Err(e) => error!("Failed to obtain GPU uploader: {e:#}"),
                 };
             }
         }
+        let overrides = self.override_store.for_output(&output_name)
+            .map(|o| Override {
+                workspace: o.workspace.clone(),
+                path: o.path.clone(),
+                persistent: true,
+            })
+            .collect::<Vec<_>>();
+        if !overrides.is_empty() {
+            debug!("Restoring {} saved wallpaper overrides for output {}",
+                overrides.len(), output_name);
+        }
         let is_dmabuf_feedback = dmabuf_feedback.is_some();
+        let shm_format = self.shm_format(options.pixelformat);
         let bg_layer_index = self.background_layers.len();
         self.background_layers.push(BackgroundLayer {
             output_name,
@@ -609,8 +1292,38 @@ impl OutputHandler for State {
             current_wallpaper: None,
             queued_wallpaper: None,
             transform: info.transform,
+            scaling,
             viewport,
             dmabuf_feedback,
+            gpu_target,
+            visible_workspace: None,
+            occupied: false,
+            shown_workspace_number: None,
+            vertical_workspaces: self.compositor.has_vertical_workspaces(),
+            time_of_day: self.config.schedule.current().0,
+            wallpaper_dirs: Vec::new(),
+            options,
+            slideshows: Vec::new(),
+            overrides,
+            failed_files: Vec::new(),
+            random_pool: RandomPool::new(
+                Vec::new(),
+                options.random_cache_size,
+                options.random_stable,
+            ),
+            shm_format,
+            random_gpu: false,
+            random_gpu_pending: None,
+            on_change: self.config.on_change.clone(),
+            subscribers: Arc::clone(&self.subscribers),
+            exporter: self.exporter.clone(),
+            transition: None,
+            pending_switch: None,
+            playback: None,
+            frame_pending: false,
+            wl_shm: self.shm.wl_shm().clone(),
+            qh: qh.clone(),
+            settled: false,
         });
         if !is_dmabuf_feedback {
             load_wallpapers(self, conn, qh, bg_layer_index, gpu_uploader);
@@ -708,6 +1421,7 @@ impl OutputHandler for State {
             if let Some(old_viewport) = bg_layer.viewport.take() {
                 old_viewport.destroy();
             };
+            bg_layer.scaling = Scaling::None;
         } else if width == logical_width * integer_scale_factor
             && height == logical_height * integer_scale_factor
         {
@@ -716,9 +1430,11 @@ impl OutputHandler for State {
             if let Some(old_viewport) = bg_layer.viewport.take() {
                 old_viewport.destroy();
             };
+            bg_layer.scaling = Scaling::Integer(integer_scale_factor);
         } else {
             debug!("Output {} needs fractional scaling", output_name);
             surface.set_buffer_scale(1);
+            bg_layer.scaling = Scaling::Viewport;
             bg_layer.viewport
                 .get_or_insert_with(||
                     self.viewporter.get_viewport(surface, qh, ())
@@ -752,6 +1468,10 @@ impl OutputHandler for State {
         };
 
         debug!("Output destroyed: {}", output_name);
+        self.subscribers.emit(Event::OutputRemoved { output: &output_name });
+        if let Some(exporter) = &self.exporter {
+            exporter.remove(&output_name);
+        }
 
         if let Some(bg_layer_index) = self.background_layers.iter()
             .position(|bg_layers| bg_layers.output_name == output_name)
@@ -772,6 +1492,7 @@ impl OutputHandler for State {
             );
 
             drop(removed_bg_layer);
+            update_dir_watches(self);
         } else {
             error!(
                 "Ignoring destroyed output with unknown name '{}', \
@@ -863,6 +1584,29 @@ impl Dispatch<WlBuffer, ()> for State {
     }
 }
 
+impl Dispatch<WlBuffer, ScratchBuffer> for State {
+    fn event(
+        state: &mut Self,
+        _proxy: &WlBuffer,
+        event: <WlBuffer as Proxy>::Event,
+        data: &ScratchBuffer,
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        if let wl_buffer::Event::Release = event {
+            data.release();
+            for bg_layer in state.background_layers.iter_mut() {
+                if let Some(transition) = bg_layer.transition.as_mut()
+                    .filter(|transition| transition.waiting_for_release)
+                {
+                    transition.waiting_for_release = false;
+                    bg_layer.step_transition();
+                }
+            }
+        }
+    }
+}
+
 fn layer_surface_name(output_name: &str) -> Option<String> {
     Some([env!("CARGO_PKG_NAME"), "_wallpaper_", output_name].concat())
 }
@@ -874,6 +1618,8 @@ fn find_equal_wallpaper(
     transform: Transform,
     wallpaper_file: &WallpaperFile,
     gpu_uploader: Option<&GpuUploader>,
+    shm_format: wl_shm::Format,
+    processing: Processing,
 ) -> Option<Rc<RefCell<Wallpaper>>> {
     for bg_layer in background_layers {
         if bg_layer.width == width
@@ -882,10 +1628,12 @@ fn find_equal_wallpaper(
         {
             for bg in &bg_layer.workspace_backgrounds {
                 let wallpaper = bg.wallpaper.borrow();
-                if wallpaper.canon_modified == wallpaper_file.canon_modified
-                    && wallpaper.canon_path == wallpaper_file.canon_path
-                    && wallpaper.memory.gpu_uploader_eq(gpu_uploader)
-                {
+                if wallpaper.is_loaded_from(
+                    wallpaper_file,
+                    gpu_uploader,
+                    shm_format,
+                    processing,
+                ) {
                     debug!("Reusing the wallpaper of output {} workspace {}",
                         bg_layer.output_name, bg.workspace_name);
                     return Some(Rc::clone(&bg.wallpaper));
@@ -896,17 +1644,80 @@ fn find_equal_wallpaper(
     None
 }
 
+// The files without the overrides, of the current variants
+fn current_files(
+    workspace_backgrounds: &[WorkspaceBackground],
+    time_of_day: TimeOfDay,
+    occupied: bool,
+) -> impl Iterator<Item = &WorkspaceBackground> {
+    workspace_backgrounds.iter()
+        .filter(move |bg| bg.override_scope.is_none()
+            && bg.is_current_variant(
+                workspace_backgrounds,
+                time_of_day,
+                occupied,
+            )
+        )
+}
+
+// The wallpaper meant for the workspace and the rule choosing it,
+// not counting the default for any workspace
+fn own_background<'a>(
+    workspace_backgrounds: &'a [WorkspaceBackground],
+    time_of_day: TimeOfDay,
+    occupied: bool,
+    workspace_name: &str,
+    workspace_number: i32,
+) -> Option<(&'a WorkspaceBackground, &'static str)> {
+    let overrides = || workspace_backgrounds.iter()
+        .filter(|bg| bg.override_scope.is_some());
+    let files = || {
+        current_files(workspace_backgrounds, time_of_day, occupied)
+    };
+    overrides()
+        .find(|bg| bg.override_scope == Some(OverrideScope::Workspace)
+            && bg.workspace_name == workspace_name)
+        .map(|bg| (bg, "override for the workspace"))
+        .or_else(|| overrides()
+            .find(|bg| bg.override_scope == Some(OverrideScope::Output))
+            .map(|bg| (bg, "override for the output"))
+        )
+        .or_else(|| files()
+            .find(|bg| bg.workspace_name == workspace_name)
+            .map(|bg| (bg, "exact name"))
+        )
+        .or_else(|| files()
+            .find(|bg| bg.workspace_number == workspace_number)
+            .map(|bg| (bg, "workspace number"))
+        )
+        .or_else(|| files()
+            .filter_map(|bg| Some((
+                bg.pattern.as_ref()?.specificity(workspace_name)?,
+                bg,
+            )))
+            // Most specific, on ties the first by name
+            .min_by(|(a, a_bg), (b, b_bg)| b.cmp(a)
+                .then(a_bg.workspace_name.cmp(&b_bg.workspace_name))
+            )
+            .map(|(_, bg)| (bg, "most specific pattern"))
+        )
+}
+
 fn find_equal_output_wallpaper(
     workspace_backgrounds: &[WorkspaceBackground],
     wallpaper_file: &WallpaperFile,
     gpu_uploader: Option<&GpuUploader>,
+    shm_format: wl_shm::Format,
+    processing: Processing,
 ) -> Option<Rc<RefCell<Wallpaper>>> {
     for bg in workspace_backgrounds {
         let wallpaper = bg.wallpaper.borrow();
-        if wallpaper.canon_modified == wallpaper_file.canon_modified
-            && wallpaper.canon_path == wallpaper_file.canon_path
-            && wallpaper.memory.gpu_uploader_eq(gpu_uploader)
-        {
+        if wallpaper.is_loaded_from(
+            wallpaper_file,
+            gpu_uploader,
+            shm_format,
+            processing,
+        ) {
             debug!("Reusing the wallpaper of workspace {}",
                 bg.workspace_name);
             return Some(Rc::clone(&bg.wallpaper));
@@ -917,32 +1728,104 @@ fn find_equal_output_wallpaper(
 
 fn print_memory_stats(background_layers: &[BackgroundLayer]) {
     if log::log_enabled!(log::Level::Debug) {
-        let mut wl_shm_count = 0.0f32;
-        let mut wl_shm_size = 0.0f32;
-        let mut dmabuf_count = 0.0f32;
-        let mut dmabuf_size = 0.0f32;
-        for bg_layer in background_layers {
-            for bg in &bg_layer.workspace_backgrounds {
-                let factor = 1.0 / Rc::strong_count(&bg.wallpaper) as f32;
-                match &bg.wallpaper.borrow().memory {
-                    Memory::WlShm { pool } => {
-                        wl_shm_count += factor;
-                        wl_shm_size += factor * pool.len() as f32;
-                    },
-                    Memory::Dmabuf { gpu_memory, .. } => {
-                        dmabuf_count += factor;
-                        dmabuf_size += factor * gpu_memory.size() as f32;
-                    },
-                }
+        debug!("{}", memory_stats(background_layers));
+    }
+}
+
+fn memory_stats(background_layers: &[BackgroundLayer]) -> String {
+    let mut wl_shm_count = 0.0f32;
+    let mut wl_shm_size = 0.0f32;
+    let mut dmabuf_count = 0.0f32;
+    let mut dmabuf_size = 0.0f32;
+    for bg_layer in background_layers {
+        let wallpapers = bg_layer.workspace_backgrounds.iter()
+            .map(|bg| &bg.wallpaper)
+            .chain(bg_layer.random_pool.cached_images());
+        for wallpaper in wallpapers {
+            let factor = 1.0 / Rc::strong_count(wallpaper) as f32;
+            match &wallpaper.borrow().memory {
+                Memory::WlShm { pool, .. } => {
+                    wl_shm_count += factor;
+                    wl_shm_size += factor * pool.len() as f32;
+                },
+                Memory::Dmabuf { gpu_memory, .. } => {
+                    dmabuf_count += factor;
+                    dmabuf_size += factor * gpu_memory.size() as f32;
+                },
             }
         }
-        let wl_shm_count = (wl_shm_count + 0.5) as usize;
-        let wl_shm_size_kb = (wl_shm_size + 0.5) as usize / 1024;
-        let dmabuf_count = (dmabuf_count + 0.5) as usize;
-        let dmabuf_size_kb = (dmabuf_size + 0.5) as usize / 1024;
-        debug!("Memory use: {wl_shm_size_kb} KiB from {wl_shm_count} wl_shm \
-            pools, {dmabuf_size_kb} KiB from {dmabuf_count} DMA-BUFs");
     }
+    let wl_shm_count = (wl_shm_count + 0.5) as usize;
+    let wl_shm_size_kb = (wl_shm_size + 0.5) as usize / 1024;
+    let dmabuf_count = (dmabuf_count + 0.5) as usize;
+    let dmabuf_size_kb = (dmabuf_size + 0.5) as usize / 1024;
+    format!("Memory use: {wl_shm_size_kb} KiB from {wl_shm_count} wl_shm \
+        pools, {dmabuf_size_kb} KiB from {dmabuf_count} DMA-BUFs")
+}
+
+pub fn print_state_report(state: &State) {
+    let mut w = String::with_capacity(4000);
+    writeln!(w, "State report, {} outputs:",
+        state.background_layers.len()).unwrap();
+    for bg_layer in &state.background_layers {
+        write!(w, "Output {}", bg_layer.output_name).unwrap();
+        if state.show_serials {
+            write!(w, " '{}'", bg_layer.output_make_model_serial).unwrap();
+        }
+        writeln!(w, ": size {}x{}, transform {:?}, scaling {}, {}",
+            bg_layer.width, bg_layer.height, bg_layer.transform,
+            bg_layer.scaling,
+            if bg_layer.configured { "configured" } else { "not configured" }
+        ).unwrap();
+        match &bg_layer.visible_workspace {
+            Some((name, number)) => writeln!(w,
+                "  visible workspace: {name} (number {number}{})",
+                if bg_layer.occupied { ", occupied" } else { "" }
+            ).unwrap(),
+            None => writeln!(w, "  visible workspace: unknown").unwrap(),
+        }
+        match &bg_layer.current_wallpaper {
+            Some(wallpaper) => writeln!(w, "  current wallpaper: {:?}",
+                wallpaper.borrow().canon_path).unwrap(),
+            None => writeln!(w, "  current wallpaper: none").unwrap(),
+        }
+        for bg in &bg_layer.workspace_backgrounds {
+            let wallpaper = bg.wallpaper.borrow();
+            let memory = match wallpaper.memory {
+                Memory::WlShm { .. } => "wl_shm",
+                Memory::Dmabuf { .. } => "DMA-BUF",
+            };
+            let ready = if wallpaper.wl_buffer.is_some() {
+                ""
+            } else {
+                ", not ready"
+            };
+            let variant = bg.variant
+                .map(|variant| format!(" at {variant}"))
+                .unwrap_or_default();
+            let occupied = if bg.occupied { " when occupied" } else { "" };
+            writeln!(w, "  workspace {}{variant}{occupied} -> {:?} \
+                ({memory}{ready})",
+                bg.workspace_name, wallpaper.canon_path).unwrap();
+        }
+        if bg_layer.random_pool.len() > 0 {
+            writeln!(w, "  random pool: {} images, {} loaded",
+                bg_layer.random_pool.len(),
+                bg_layer.random_pool.cached_images().count()
+            ).unwrap();
+        }
+        let now = Instant::now();
+        for slideshow in &bg_layer.slideshows {
+            writeln!(w, "  slideshow {}: image {} of {}, next in {}s",
+                slideshow.workspace_name, slideshow.current() + 1,
+                slideshow.len(),
+                slideshow.next_switch().saturating_duration_since(now)
+                    .as_secs()
+            ).unwrap();
+        }
+    }
+    write!(w, "{}", memory_stats(&state.background_layers)).unwrap();
+    info!("{}", w);
 }
 
 fn fallback_shm_load_wallpapers(
@@ -955,6 +1838,7 @@ fn fallback_shm_load_wallpapers(
     if let Some(dmabuf_feedback) = bg_layer.dmabuf_feedback.take() {
         dmabuf_feedback.destroy();
     }
+    bg_layer.gpu_target = None;
     bg_layer.workspace_backgrounds.clear();
     load_wallpapers(state, conn, qh, bg_layer_index, None);
 }
@@ -968,13 +1852,43 @@ fn load_wallpapers(
 ) {
     let bg_layer = &state.background_layers[bg_layer_index];
     let wallpaper_dir = &state.wallpaper_dir;
-    let output_name = bg_layer.output_name.as_str();
+    let output_name = bg_layer.output_name.clone();
     let width = bg_layer.width;
     let height = bg_layer.height;
     let transform = bg_layer.transform;
-    let output_dir = wallpaper_dir.join(output_name);
-    debug!("Looking for wallpapers for new output {} in {:?}",
+    let options = bg_layer.options;
+    let config_wallpaper_files = state.config.wallpaper_files(
+        &output_name,
+        &bg_layer.output_make_model_serial,
+    );
+    let mut failed_files = Vec::new();
+    let mut override_files = Vec::new();
+    for Override { workspace, path, .. } in bg_layer.overrides.clone() {
+        let scope = match workspace {
+            Some(_) => OverrideScope::Workspace,
+            None => OverrideScope::Output,
+        };
+        let workspace = workspace.unwrap_or_default();
+        match wallpaper_file(path.clone(), workspace.clone()) {
+            Ok(mut wallpaper_file) => {
+                // Overrides are for the exact workspace at any time of day,
+                // with or without windows
+                wallpaper_file.workspace = workspace;
+                wallpaper_file.variant = None;
+                wallpaper_file.occupied = false;
+                wallpaper_file.pattern = None;
+                override_files.push((wallpaper_file, Some(scope)));
+            },
+            Err(e) => {
+                error!("Override wallpaper: {e:#}");
+                failed_files.push((path, format!("{e:#}")));
+            },
+        }
+    }
+    let output_dir = wallpaper_dir.join(&output_name);
+    debug!("Looking for wallpapers for output {} in {:?}",
         output_name, output_dir);
+    let mut found_output_dir = output_dir.clone();
     let mut wallpaper_files = output_wallpaper_files(&output_dir);
     if wallpaper_files.is_err() {
         // Try the other output directory based on make-model-serial
@@ -984,40 +1898,129 @@ fn load_wallpapers(
                 &bg_layer.output_make_model_serial
             );
             if state.show_serials {
-                debug!("Looking for wallpapers for new output {} in {:?}",
+                debug!("Looking for wallpapers for output {} in {:?}",
                     output_name, output_dir);
             } else {
-                debug!("Looking for wallpapers for new output {} in \
+                debug!("Looking for wallpapers for output {} in \
                     'wallpaper_dir/MAKE MODEL SERIAL'", output_name);
             }
             if let Ok(files) = output_wallpaper_files(&output_dir) {
                 wallpaper_files = Ok(files);
+                found_output_dir = output_dir;
             }
         }
     }
-    let wallpaper_files = match wallpaper_files {
+    if wallpaper_files.is_err() {
+        // Try the directories named as globs or make-model prefixes
+        if let Some(found) = find_output_dir(
+            wallpaper_dir,
+            &output_name,
+            &bg_layer.output_make_model_serial,
+        ) {
+            let output_dir = wallpaper_dir.join(&found.name);
+            let others = match found.candidates - 1 {
+                0 => String::new(),
+                n => format!(", the most specific of {} matching", n + 1),
+            };
+            if !found.by_make_model_serial || state.show_serials {
+                debug!("Looking for wallpapers for output {} in {:?}{}",
+                    output_name, output_dir, others);
+            } else {
+                debug!("Looking for wallpapers for output {} in \
+                    'wallpaper_dir/MAKE MODEL SERIAL' pattern{}",
+                    output_name, others);
+            }
+            match output_wallpaper_files(&output_dir) {
+                Ok(files) => {
+                    wallpaper_files = Ok(files);
+                    found_output_dir = output_dir;
+                },
+                Err(e) => error!("Failed to get wallpapers for output {} \
+                    from matching directory: {:#}", output_name, e),
+            }
+        }
+    }
+    if wallpaper_files.is_err() {
+        // Fall back to the directory for any output without its own
+        let output_dir = wallpaper_dir.join("_default");
+        debug!("Looking for wallpapers for output {} in {:?}",
+            output_name, output_dir);
+        if let Ok(files) = output_wallpaper_files(&output_dir) {
+            wallpaper_files = Ok(files);
+            found_output_dir = output_dir;
+        }
+    }
+    let mut wallpaper_files = match wallpaper_files {
         Ok(wallpaper_files) => wallpaper_files,
+        Err(e) if !config_wallpaper_files.is_empty()
+            || !override_files.is_empty() =>
+        {
+            debug!("Using only the configured wallpapers for output {}, \
+                failed to read {:?}: {:#}", output_name, output_dir, e);
+            Vec::new()
+        },
         Err(e) => {
-            error!("Failed to get wallpapers for new output {output_name} \
+            error!("Failed to get wallpapers for output {output_name} \
                 form {output_dir:?}: {e:#}");
+            if let Some(sd_notify) = &state.sd_notify {
+                sd_notify.notify(&format!("STATUS=Failed to get wallpapers \
+                    for output {output_name}"));
+            }
+            state.subscribers.emit(Event::WallpaperFailed {
+                output: &output_name,
+                path: &output_dir,
+                error: &format!("{e:#}"),
+            });
+            let bg_layer = &mut state.background_layers[bg_layer_index];
+            bg_layer.workspace_backgrounds.clear();
+            bg_layer.random_pool = RandomPool::new(
+                Vec::new(),
+                options.random_cache_size,
+                options.random_stable,
+            );
+            bg_layer.random_gpu_pending = None;
+            bg_layer.wallpaper_dirs.clear();
+            bg_layer.slideshows.clear();
+            bg_layer.failed_files = failed_files;
+            update_dir_watches(state);
+            update_slideshow_timer(state);
+            update_time_of_day_timer(state);
             return
         }
     };
-    let shm_format = state.shm_format();
-    let shm_stride = match shm_format {
-        wl_shm::Format::Xrgb8888 => width as usize * 4,
-        wl_shm::Format::Bgr888 => {
-            // Align buffer stride:
-            // - once to 4, because not being aligned to 4 caused
-            //   https://github.com/gergo-salyi/multibg-wayland/issues/6
-            // - and to 3, because not being aligned to 3 caused
-            //   https://github.com/gergo-salyi/multibg-wayland/issues/17
-            // So align stride to 4 * 3 = 12
-            (width as usize * 3).next_multiple_of(12)
-        },
-        _ => unreachable!(),
-    };
-    let shm_size = shm_stride * height as usize;
+    // Wallpapers from the configuration file override
+    // the ones for the same workspace in the output directory
+    wallpaper_files.retain(|wallpaper_file| !config_wallpaper_files.iter()
+        .any(|config_file| config_file.workspace == wallpaper_file.workspace
+            && config_file.variant == wallpaper_file.variant
+            && config_file.occupied == wallpaper_file.occupied
+        )
+    );
+    // Watch the resolved output directory and the directories
+    // of symlink targets for changes
+    let mut wallpaper_dirs = Vec::new();
+    if let Ok(canon_output_dir) = found_output_dir.canonicalize() {
+        wallpaper_dirs.push(canon_output_dir);
+    }
+    wallpaper_files.extend(config_wallpaper_files);
+    // Overrides go ahead of the wallpapers from files
+    let wallpaper_files = override_files.into_iter()
+        .chain(wallpaper_files.into_iter().map(|file| (file, None)))
+        .collect::<Vec<_>>();
+    for (wallpaper_file, _) in &wallpaper_files {
+        if let Some(parent) = wallpaper_file.canon_path.parent() {
+            if !wallpaper_dirs.iter().any(|dir| dir == parent) {
+                wallpaper_dirs.push(parent.to_path_buf());
+            }
+        }
+    }
+    // Images of the random pool are loaded only when picked
+    let (random_files, wallpaper_files): (Vec<_>, Vec<_>) = wallpaper_files
+        .into_iter()
+        .partition(|(wallpaper_file, override_scope)| override_scope.is_none()
+            && wallpaper_file.workspace == "_random"
+        );
+    let shm_format = state.shm_format(options.pixelformat);
     let mut workspace_backgrounds = Vec::new();
     let mut resizer = fast_image_resize::Resizer::new();
     let mut reused_count = 0usize;
@@ -1025,7 +2028,8 @@ fn load_wallpapers(
     let mut error_count = 0usize;
     flush_blocking(connection);
     let mut fds_need_flush = 0usize;
-    for wallpaper_file in wallpaper_files {
+    for (wallpaper_file, override_scope) in wallpaper_files {
+        let processing = file_processing(&options, &wallpaper_file);
         if log::log_enabled!(log::Level::Debug) {
             if wallpaper_file.path == wallpaper_file.canon_path {
                 debug!("Wallpaper file {:?} for workspace {}",
@@ -1040,10 +2044,17 @@ fn load_wallpapers(
             &workspace_backgrounds,
             &wallpaper_file,
             gpu_uploader.as_ref(),
+            shm_format,
+            processing,
         ) {
             workspace_backgrounds.push(WorkspaceBackground {
                 workspace_name: wallpaper_file.workspace,
                 workspace_number: wallpaper_file.workspace_number,
+                pattern: wallpaper_file.pattern,
+                override_scope,
+                variant: wallpaper_file.variant,
+                occupied: wallpaper_file.occupied,
+                path: wallpaper_file.path,
                 wallpaper,
             });
             reused_count += 1;
@@ -1056,10 +2067,17 @@ fn load_wallpapers(
             transform,
             &wallpaper_file,
             gpu_uploader.as_ref(),
+            shm_format,
+            processing,
         ) {
             workspace_backgrounds.push(WorkspaceBackground {
                 workspace_name: wallpaper_file.workspace,
                 workspace_number: wallpaper_file.workspace_number,
+                pattern: wallpaper_file.pattern,
+                override_scope,
+                variant: wallpaper_file.variant,
+                occupied: wallpaper_file.occupied,
+                path: wallpaper_file.path,
                 wallpaper,
             });
             reused_count += 1;
@@ -1073,10 +2091,11 @@ fn load_wallpapers(
                 height as u32,
                 width as usize * 4,
                 wl_shm::Format::Xrgb8888,
-                state.color_transform,
+                processing,
                 &mut resizer,
             ) {
                 error!("Failed to load wallpaper: {e:#}");
+                failed_files.push((wallpaper_file.path, format!("{e:#}")));
                 error_count += 1;
                 continue
             }
@@ -1096,10 +2115,16 @@ fn load_wallpapers(
                         height,
                         wallpaper_file.canon_path,
                         wallpaper_file.canon_modified,
+                        processing,
                     );
                     workspace_backgrounds.push(WorkspaceBackground {
                         workspace_name: wallpaper_file.workspace,
                         workspace_number: wallpaper_file.workspace_number,
+                        pattern: wallpaper_file.pattern,
+                        override_scope,
+                        variant: wallpaper_file.variant,
+                        occupied: wallpaper_file.occupied,
+                        path: wallpaper_file.path,
                         wallpaper,
                     });
                     loaded_count += 1;
@@ -1117,65 +2142,725 @@ fn load_wallpapers(
             fds_need_flush = 0;
         }
         fds_need_flush += 1;
-        let mut shm_pool = match RawPool::new(shm_size, &state.shm) {
-            Ok(shm_pool) => shm_pool,
-            Err(e) => {
-                error!("Failed to create shm pool: {e}");
-                error_count += 1;
-                continue
-            }
-        };
-        if let Err(e) = load_wallpaper(
-            &wallpaper_file.path,
-            shm_pool.mmap(),
-            width as u32,
-            height as u32,
-            shm_stride,
+        match load_shm_wallpaper(
+            &wallpaper_file,
+            &state.shm,
+            qh,
+            width,
+            height,
             shm_format,
-            state.color_transform,
+            processing,
+            &options,
             &mut resizer,
         ) {
-            error!("Failed to load wallpaper: {e:#}");
-            error_count += 1;
-            continue
+            Ok(wallpaper) => workspace_backgrounds.push(WorkspaceBackground {
+                workspace_name: wallpaper_file.workspace,
+                workspace_number: wallpaper_file.workspace_number,
+                pattern: wallpaper_file.pattern,
+                override_scope,
+                variant: wallpaper_file.variant,
+                occupied: wallpaper_file.occupied,
+                path: wallpaper_file.path,
+                wallpaper,
+            }),
+            Err(e) => {
+                error!("Failed to load wallpaper: {e:#}");
+                failed_files.push((wallpaper_file.path, format!("{e:#}")));
+                error_count += 1;
+                continue
+            },
         }
-        let wl_buffer = shm_pool.create_buffer(
-            0,
-            width,
-            height,
-            shm_stride.try_into().unwrap(),
-            shm_format,
-            (),
-            qh,
-        );
-        workspace_backgrounds.push(WorkspaceBackground {
-            workspace_name: wallpaper_file.workspace,
-            workspace_number: wallpaper_file.workspace_number,
-            wallpaper: Rc::new(RefCell::new(Wallpaper {
-                wl_buffer: Some(wl_buffer),
-                // active_count: 0,
-                memory: Memory::WlShm { pool: shm_pool },
-                canon_path: wallpaper_file.canon_path,
-                canon_modified: wallpaper_file.canon_modified,
-            })),
-        });
         loaded_count += 1;
     }
     if fds_need_flush > 0 {
         flush_blocking(connection);
     }
-    debug!("Wallpapers for new output: {} loaded, {} reused, {} errors",
-        loaded_count, reused_count, error_count);
+    debug!("Wallpapers for output {}: {} loaded, {} reused, {} errors",
+        output_name, loaded_count, reused_count, error_count);
+    if let Some(sd_notify) = &state.sd_notify {
+        sd_notify.notify(&format!("STATUS=Wallpapers for output {}: \
+            {} loaded, {} reused, {} errors",
+            output_name, loaded_count, reused_count, error_count));
+    }
+    for (path, error) in &failed_files {
+        state.subscribers.emit(Event::WallpaperFailed {
+            output: &output_name,
+            path,
+            error,
+        });
+    }
+    state.subscribers.emit(Event::WallpapersLoaded {
+        output: &output_name,
+        loaded: loaded_count,
+        reused: reused_count,
+        errors: error_count,
+    });
     debug!("Wallpapers are available for workspaces: {}",
         workspace_backgrounds.iter()
             .map(|bg| bg.workspace_name.as_str())
             .collect::<Vec<_>>().join(", "));
-    state.background_layers[bg_layer_index].workspace_backgrounds =
-        workspace_backgrounds;
+    let bg_layer = &mut state.background_layers[bg_layer_index];
+    let mut slideshows = Vec::<Slideshow>::new();
+    let file_backgrounds = || workspace_backgrounds.iter()
+        .filter(|bg| bg.override_scope.is_none());
+    // Each variant of a workspace has its own slideshow
+    let same_images = |bg: &WorkspaceBackground, slideshow: &Slideshow|
+        slideshow.shows(&bg.workspace_name, bg.variant, bg.occupied);
+    for bg in file_backgrounds() {
+        let name = &bg.workspace_name;
+        if slideshows.iter().any(|slideshow| same_images(bg, slideshow)) {
+            continue
+        }
+        let len = file_backgrounds()
+            .filter(|other| other.workspace_name == bg.workspace_name
+                && other.variant == bg.variant
+                && other.occupied == bg.occupied
+            )
+            .count();
+        if len < 2 {
+            continue
+        }
+        let mut slideshow = Slideshow::new(
+            name.clone(),
+            bg.variant,
+            bg.occupied,
+            len,
+            options.slideshow_order,
+            options.slideshow_interval,
+            &mut state.rng,
+        );
+        if let Some(previous) = bg_layer.slideshows.iter()
+            .find(|previous| same_images(bg, previous))
+        {
+            slideshow.continue_from(previous);
+        }
+        debug!("Slideshow of {} images for output {} workspace {}",
+            len, output_name, name);
+        slideshows.push(slideshow);
+    }
+    let mut random_pool = RandomPool::new(
+        random_files.into_iter().map(|(file, _)| file).collect(),
+        options.random_cache_size,
+        options.random_stable,
+    );
+    random_pool.continue_from(&bg_layer.random_pool);
+    if random_pool.len() > 0 {
+        debug!("Random pool of {} images for output {}",
+            random_pool.len(), output_name);
+    }
+    bg_layer.workspace_backgrounds = workspace_backgrounds;
+    bg_layer.random_pool = random_pool;
+    bg_layer.shm_format = shm_format;
+    bg_layer.random_gpu = gpu_uploader.is_some();
+    bg_layer.random_gpu_pending = None;
+    bg_layer.wallpaper_dirs = wallpaper_dirs;
+    bg_layer.slideshows = slideshows;
+    bg_layer.failed_files = failed_files;
+    update_dir_watches(state);
+    update_slideshow_timer(state);
+    update_time_of_day_timer(state);
     malloc_trim_all();
     print_memory_stats(&state.background_layers);
 }
 
+fn file_processing(
+    options: &OutputOptions,
+    wallpaper_file: &WallpaperFile,
+) -> Processing {
+    Processing {
+        color_transform: options.color_transform,
+        fit: wallpaper_file.fit.unwrap_or(options.fit),
+        background: wallpaper_file.background_color
+            .unwrap_or(options.background_color),
+    }
+}
+
+fn shm_stride(shm_format: wl_shm::Format, width: i32) -> usize {
+    match shm_format {
+        wl_shm::Format::Xrgb8888 => width as usize * 4,
+        wl_shm::Format::Bgr888 => {
+            // Align buffer stride:
+            // - once to 4, because not being aligned to 4 caused
+            //   https://github.com/gergo-salyi/multibg-wayland/issues/6
+            // - and to 3, because not being aligned to 3 caused
+            //   https://github.com/gergo-salyi/multibg-wayland/issues/17
+            // So align stride to 4 * 3 = 12
+            (width as usize * 3).next_multiple_of(12)
+        },
+        _ => unreachable!(),
+    }
+}
+
+// Load a wallpaper with the frames of its animation into a new shm pool
+fn load_shm_wallpaper(
+    wallpaper_file: &WallpaperFile,
+    shm: &impl ProvidesBoundGlobal<WlShm, 1>,
+    qh: &QueueHandle<State>,
+    width: i32,
+    height: i32,
+    shm_format: wl_shm::Format,
+    processing: Processing,
+    options: &OutputOptions,
+    resizer: &mut fast_image_resize::Resizer,
+) -> anyhow::Result<Rc<RefCell<Wallpaper>>> {
+    let shm_stride = shm_stride(shm_format, width);
+    let shm_size = shm_stride * height as usize;
+    // Animation frames follow each other in the same shm pool
+    let max_frames = options.animation_max_frames
+        .min(options.animation_max_memory / shm_size);
+    let mut shm_pool = RawPool::new(shm_size, shm)
+        .context("Failed to create shm pool")?;
+    let mut frame_count = 1;
+    let mut delays = Vec::new();
+    let mut first_frame_only = false;
+    let path = &wallpaper_file.path;
+    if max_frames < 2 || path.extension().is_some_and(|ext| ext == "color") {
+        load_wallpaper(
+            path,
+            shm_pool.mmap(),
+            width as u32,
+            height as u32,
+            shm_stride,
+            shm_format,
+            processing,
+            resizer,
+        )?;
+    } else {
+        match open_image_frames(path)? {
+            ImageFrames::Still(decoder) => draw_decoder(
+                decoder,
+                shm_pool.mmap(),
+                width as u32,
+                height as u32,
+                shm_stride,
+                shm_format,
+                processing,
+                resizer,
+            )?,
+            ImageFrames::Animated(frames) => {
+                frame_count = 0;
+                // Draw each frame as soon as it is decoded
+                // to never hold more than one decoded frame in memory
+                for frame in frames {
+                    let frame = match frame {
+                        Ok(frame) => frame,
+                        Err(e) if frame_count > 0 => {
+                            warn!("Showing only the first frame of {path:?}: \
+                                Failed to decode animation frame: {e}");
+                            first_frame_only = true;
+                            break
+                        },
+                        Err(e) => return Err(e)
+                            .context("Failed to decode animation frame"),
+                    };
+                    if frame_count == max_frames {
+                        warn!("Showing only the first frame of {path:?}: \
+                            Animation has more than the limit \
+                            of {max_frames} frames");
+                        first_frame_only = true;
+                        break
+                    }
+                    if frame_count > 0 {
+                        shm_pool.resize(shm_size * (frame_count + 1))
+                            .context("Failed to resize shm pool")?;
+                    }
+                    delays.push(frame_delay(&frame));
+                    draw_image(
+                        DynamicImage::ImageRgba8(frame.into_buffer()),
+                        &mut shm_pool.mmap()[frame_count * shm_size..],
+                        width as u32,
+                        height as u32,
+                        shm_stride,
+                        shm_format,
+                        processing,
+                        resizer,
+                    )?;
+                    frame_count += 1;
+                }
+                if frame_count == 0 {
+                    bail!("Animation has no frames")
+                }
+                if first_frame_only && frame_count > 1 {
+                    // Keep only the first frame, pools can not shrink
+                    let mut first_pool = RawPool::new(shm_size, shm)
+                        .context("Failed to create shm pool")?;
+                    first_pool.mmap()[..shm_size]
+                        .copy_from_slice(&shm_pool.mmap()[..shm_size]);
+                    shm_pool = first_pool;
+                    frame_count = 1;
+                    delays.clear();
+                } else if frame_count > 1 {
+                    debug!("Animation of {frame_count} frames");
+                }
+            },
+        }
+    }
+    let mut create_buffer = |index: usize| shm_pool.create_buffer(
+        (index * shm_size).try_into().unwrap(),
+        width,
+        height,
+        shm_stride.try_into().unwrap(),
+        shm_format,
+        (),
+        qh,
+    );
+    let wl_buffer = create_buffer(0);
+    let animation = (frame_count > 1).then(|| Animation::new(
+        (1..frame_count).map(create_buffer).collect(),
+        delays,
+    ));
+    Ok(Rc::new(RefCell::new(Wallpaper {
+        wl_buffer: Some(wl_buffer),
+        // active_count: 0,
+        memory: Memory::WlShm {
+            pool: shm_pool,
+            format: shm_format,
+            stride: shm_stride,
+        },
+        canon_path: wallpaper_file.canon_path.clone(),
+        canon_modified: wallpaper_file.canon_modified,
+        processing,
+        animation,
+    })))
+}
+
+fn update_dir_watches(state: &mut State) {
+    let Some(dir_watcher) = state.dir_watcher.as_mut() else { return };
+    dir_watcher.watch_dirs(
+        std::iter::once(state.wallpaper_dir.as_path())
+            .chain(state.background_layers.iter()
+                .flat_map(|bg_layer| &bg_layer.wallpaper_dirs)
+                .map(PathBuf::as_path)
+            )
+    );
+}
+
+fn update_slideshow_timer(state: &mut State) {
+    let Some(timer) = state.slideshow_timer.as_ref() else { return };
+    timer.set_deadline(state.background_layers.iter()
+        .flat_map(|bg_layer| &bg_layer.slideshows)
+        .map(Slideshow::next_switch)
+        .min()
+    );
+}
+
+/// Switch to the next image of the slideshows which are due
+pub fn advance_slideshows(state: &mut State) {
+    let now = Instant::now();
+    for bg_layer in state.background_layers.iter_mut() {
+        let mut changed = false;
+        for slideshow in bg_layer.slideshows.iter_mut() {
+            if slideshow.advance(now, &mut state.rng) {
+                debug!("Slideshow on output {} workspace {} switched \
+                    to image {}", bg_layer.output_name,
+                    slideshow.workspace_name, slideshow.current());
+                changed = true;
+            }
+        }
+        if changed && !state.paused {
+            bg_layer.redraw();
+        }
+    }
+    update_slideshow_timer(state);
+}
+
+/// Switch to the wallpaper variants of the time of day if it has changed
+pub fn advance_time_of_day(state: &mut State) {
+    let (time_of_day, _) = state.config.schedule.current();
+    for bg_layer in state.background_layers.iter_mut() {
+        if bg_layer.time_of_day == time_of_day {
+            continue
+        }
+        debug!("Time of day changed to {time_of_day} on output {}",
+            bg_layer.output_name);
+        bg_layer.time_of_day = time_of_day;
+        if bg_layer.has_variants() && !state.paused {
+            bg_layer.redraw();
+        }
+    }
+    update_time_of_day_timer(state);
+}
+
+fn update_time_of_day_timer(state: &State) {
+    let Some(timer) = state.time_of_day_timer.as_ref() else { return };
+    if !state.background_layers.iter().any(BackgroundLayer::has_variants) {
+        timer.set_deadline(None);
+        return
+    }
+    let (_, next_change) = state.config.schedule.current();
+    // Check again at least this often to follow suspends
+    // and changes of the clock
+    let max_wait = Duration::from_secs(15 * 60);
+    timer.set_deadline(Some(Instant::now()
+        + next_change.map_or(max_wait, |next| next.min(max_wait))
+    ));
+}
+
+/// Show the next frame of the animations which are due
+pub fn advance_animations(state: &mut State) {
+    let now = Instant::now();
+    for bg_layer in state.background_layers.iter_mut() {
+        bg_layer.step_animation(now);
+    }
+}
+
+/// Wake up for the next animation frame, unless all animations
+/// are waiting for frame callbacks
+pub fn update_animation_timer(state: &State) {
+    let Some(timer) = state.animation_timer.as_ref() else { return };
+    timer.set_deadline(state.background_layers.iter()
+        .filter_map(BackgroundLayer::next_animation_frame)
+        .min()
+    );
+}
+
+fn output_index(state: &State, output_name: &str) -> anyhow::Result<usize> {
+    state.background_layers.iter()
+        .position(|bg_layer| bg_layer.output_name == output_name)
+        .with_context(|| format!("Unknown output {output_name}, \
+            known outputs are: {}", state.background_layers.iter()
+                .map(|bg_layer| bg_layer.output_name.as_str())
+                .collect::<Vec<_>>().join(", ")))
+}
+
+/// Show the wallpaper from the path on a workspace of an output,
+/// or on all of its workspaces, until the override is cleared.
+/// Persistent overrides are saved and restored after restarts.
+pub fn set_override(
+    state: &mut State,
+    conn: &Connection,
+    qh: &QueueHandle<State>,
+    output_name: &str,
+    workspace: Option<String>,
+    path: PathBuf,
+    persist: bool,
+) -> anyhow::Result<()> {
+    if !path.is_absolute() {
+        bail!("Wallpaper path {path:?} is not absolute");
+    }
+    // Fail early without touching the current overrides
+    // if the file does not exist
+    wallpaper_file(path.clone(), String::new())?;
+    let bg_layer_index = output_index(state, output_name)?;
+    let bg_layer = &mut state.background_layers[bg_layer_index];
+    // Kept to be restored if the new wallpaper fails to load
+    let previous = bg_layer.overrides.iter()
+        .position(|o| o.workspace == workspace)
+        .map(|index| (index, bg_layer.overrides.remove(index)));
+    bg_layer.overrides.push(Override {
+        workspace: workspace.clone(),
+        path: path.clone(),
+        persistent: persist,
+    });
+    info!("Overriding the wallpaper for output {} {} with {:?}",
+        output_name,
+        match &workspace {
+            Some(workspace) => format!("workspace {workspace}"),
+            None => "all workspaces".to_string(),
+        },
+        path);
+    reload_wallpapers(state, conn, qh, bg_layer_index);
+    let bg_layer = &mut state.background_layers[bg_layer_index];
+    if let Some((_, error)) = bg_layer.failed_files.iter()
+        .find(|(failed_path, _)| *failed_path == path)
+    {
+        let error = error.clone();
+        bg_layer.overrides.retain(|o| o.workspace != workspace);
+        if let Some((index, previous)) = previous {
+            bg_layer.overrides.insert(index, previous);
+        }
+        reload_wallpapers(state, conn, qh, bg_layer_index);
+        if !state.paused {
+            state.background_layers[bg_layer_index].redraw();
+        }
+        bail!("Failed to load wallpaper {path:?}: {error}");
+    }
+    if !state.paused {
+        bg_layer.redraw();
+    }
+    if persist {
+        state.override_store.add(PersistentOverride {
+            output: output_name.to_string(),
+            workspace,
+            path,
+        });
+    } else {
+        // A temporary override replaces a saved one of the same scope
+        state.override_store.remove(
+            Some(output_name),
+            workspace.as_deref(),
+            true,
+        );
+    }
+    Ok(())
+}
+
+/// Clear the overrides of a workspace, or all overrides of the output
+/// if no workspace is given, or of all outputs if no output is given,
+/// including the saved ones. Returns the number of cleared overrides.
+pub fn clear_overrides(
+    state: &mut State,
+    output_name: Option<&str>,
+    workspace: Option<&str>,
+) -> anyhow::Result<usize> {
+    let removed = state.override_store.remove(output_name, workspace, false);
+    let indices = match output_name {
+        // Saved overrides can be cleared for disconnected outputs too
+        Some(output_name) => match output_index(state, output_name) {
+            Ok(bg_layer_index) => vec![bg_layer_index],
+            Err(_) if removed > 0 => return Ok(removed),
+            Err(e) => return Err(e),
+        },
+        None => (0..state.background_layers.len()).collect(),
+    };
+    let mut cleared = 0;
+    for bg_layer_index in indices {
+        let bg_layer = &mut state.background_layers[bg_layer_index];
+        let matches = |override_workspace: Option<&str>| {
+            workspace.is_none() || override_workspace == workspace
+        };
+        let count = bg_layer.overrides.len();
+        bg_layer.overrides.retain(|o| !matches(o.workspace.as_deref()));
+        if bg_layer.overrides.len() == count {
+            continue
+        }
+        cleared += count - bg_layer.overrides.len();
+        bg_layer.workspace_backgrounds.retain(|bg| match bg.override_scope {
+            None => true,
+            Some(OverrideScope::Workspace) => {
+                !matches(Some(&bg.workspace_name))
+            },
+            Some(OverrideScope::Output) => !matches(None),
+        });
+        info!("Cleared wallpaper overrides for output {}",
+            bg_layer.output_name);
+        if !state.paused {
+            bg_layer.redraw();
+        }
+    }
+    Ok(cleared)
+}
+
+/// Stop or continue following workspace switches,
+/// showing the wallpapers of the visible workspaces when continuing
+pub fn set_paused(state: &mut State, paused: bool) {
+    if state.paused == paused {
+        return
+    }
+    state.paused = paused;
+    if paused {
+        info!("Paused, wallpapers no longer follow workspace switches");
+    } else {
+        info!("Resumed following workspace switches");
+        for bg_layer in state.background_layers.iter_mut() {
+            bg_layer.redraw();
+        }
+    }
+}
+
+pub fn toggle_paused(state: &mut State) {
+    let paused = state.paused;
+    set_paused(state, !paused);
+}
+
+pub fn state_json(state: &State) -> serde_json::Value {
+    let outputs = state.background_layers.iter().map(|bg_layer| {
+        let workspaces = bg_layer.workspace_backgrounds.iter().map(|bg| {
+            let wallpaper = bg.wallpaper.borrow();
+            json!({
+                "workspace": bg.workspace_name,
+                "time_of_day": bg.variant.map(|variant| variant.to_string()),
+                "occupied": bg.occupied,
+                "path": wallpaper.canon_path,
+                "override": match bg.override_scope {
+                    None => None,
+                    Some(OverrideScope::Workspace) => Some("workspace"),
+                    Some(OverrideScope::Output) => Some("output"),
+                },
+                "memory": match wallpaper.memory {
+                    Memory::WlShm { .. } => "wl_shm",
+                    Memory::Dmabuf { .. } => "dmabuf",
+                },
+                "ready": wallpaper.wl_buffer.is_some(),
+            })
+        }).collect::<Vec<_>>();
+        let slideshows = bg_layer.slideshows.iter().map(|slideshow| json!({
+            "workspace": slideshow.workspace_name,
+            "image": slideshow.current(),
+            "images": slideshow.len(),
+        })).collect::<Vec<_>>();
+        let random_pool = json!({
+            "images": bg_layer.random_pool.len(),
+            "loaded": bg_layer.random_pool.cached_images().count(),
+        });
+        let overrides = bg_layer.overrides.iter().map(|o| json!({
+            "workspace": o.workspace,
+            "path": o.path,
+            "persistent": o.persistent,
+        })).collect::<Vec<_>>();
+        let failed_files = bg_layer.failed_files.iter()
+            .map(|(path, error)| json!({ "path": path, "error": error }))
+            .collect::<Vec<_>>();
+        let mut output = json!({
+            "name": bg_layer.output_name,
+            "width": bg_layer.width,
+            "height": bg_layer.height,
+            "configured": bg_layer.configured,
+            "time_of_day": bg_layer.time_of_day.to_string(),
+            "visible_workspace": bg_layer.visible_workspace.as_ref()
+                .map(|(name, number)| json!({
+                    "name": name,
+                    "number": number,
+                    "occupied": bg_layer.occupied,
+                })),
+            "current_wallpaper": bg_layer.current_wallpaper.as_ref()
+                .map(|wallpaper| wallpaper.borrow().canon_path.clone()),
+            "workspaces": workspaces,
+            "slideshows": slideshows,
+            "random_pool": random_pool,
+            "overrides": overrides,
+            "failed_files": failed_files,
+        });
+        if state.show_serials {
+            output["make_model_serial"] =
+                bg_layer.output_make_model_serial.clone().into();
+        }
+        output
+    }).collect::<Vec<_>>();
+    json!({
+        "paused": state.paused,
+        "outputs": outputs,
+    })
+}
+
+pub fn reload_changed_wallpapers(
+    state: &mut State,
+    conn: &Connection,
+    qh: &QueueHandle<State>,
+    changed_dirs: &[PathBuf],
+) {
+    // New or removed entries in wallpaper_dir might change
+    // which output directory any of the outputs should use
+    let wallpaper_dir_changed = changed_dirs.contains(&state.wallpaper_dir);
+    for bg_layer_index in 0..state.background_layers.len() {
+        let bg_layer = &state.background_layers[bg_layer_index];
+        if wallpaper_dir_changed || bg_layer.wallpaper_dirs.iter()
+            .any(|dir| changed_dirs.contains(dir))
+        {
+            debug!("Wallpaper files changed for output {}",
+                bg_layer.output_name);
+            reload_wallpapers(state, conn, qh, bg_layer_index);
+            if !state.paused {
+                state.background_layers[bg_layer_index].redraw();
+            }
+        }
+    }
+}
+
+pub fn reload_all_wallpapers(
+    state: &mut State,
+    conn: &Connection,
+    qh: &QueueHandle<State>,
+) {
+    match state.config.reload() {
+        Ok(config) => state.config = config,
+        Err(e) => error!("{e:#}, keeping the previous configuration"),
+    }
+    for bg_layer_index in 0..state.background_layers.len() {
+        // Outputs might have been renamed to match
+        // an other make-model-serial wallpaper directory
+        let output_make_model_serial = state.compositor_connection_task
+            .request_make_model_serial(
+                &state.background_layers[bg_layer_index].output_name
+            );
+        let bg_layer = &mut state.background_layers[bg_layer_index];
+        if state.show_serials
+            && bg_layer.output_make_model_serial != output_make_model_serial
+        {
+            debug!("Output {} make-model-serial changed to '{}'",
+                bg_layer.output_name, output_make_model_serial);
+        }
+        bg_layer.output_make_model_serial = output_make_model_serial;
+        let options = state.config.output_options(
+            &bg_layer.output_name,
+            &bg_layer.output_make_model_serial,
+        );
+        if options.gpu != bg_layer.options.gpu {
+            warn!("Changing the gpu option for output {} needs a restart",
+                bg_layer.output_name);
+        }
+        bg_layer.options = OutputOptions {
+            gpu: bg_layer.options.gpu,
+            ..options
+        };
+        bg_layer.on_change = state.config.on_change.clone();
+        bg_layer.time_of_day = state.config.schedule.current().0;
+        reload_wallpapers(state, conn, qh, bg_layer_index);
+    }
+    state.compositor_connection_task.request_visible_workspaces();
+    state.subscribers.emit(Event::Reloaded);
+}
+
+// Rebuild the workspace wallpapers of an output reusing the already
+// loaded wallpapers which have not changed
+fn reload_wallpapers(
+    state: &mut State,
+    conn: &Connection,
+    qh: &QueueHandle<State>,
+    bg_layer_index: usize,
+) {
+    let bg_layer = &state.background_layers[bg_layer_index];
+    if bg_layer.dmabuf_feedback.is_some() && bg_layer.gpu_target.is_none() {
+        debug!("Output {} is still waiting for DMA-BUF feedback, \
+            wallpapers will be loaded then", bg_layer.output_name);
+        return
+    }
+    let gpu_uploader = gpu_uploader(state, bg_layer_index);
+    load_wallpapers(state, conn, qh, bg_layer_index, gpu_uploader);
+}
+
+// Uploader for an output with its wallpapers in GPU memory
+fn gpu_uploader(
+    state: &mut State,
+    bg_layer_index: usize,
+) -> Option<GpuUploader> {
+    let bg_layer = &state.background_layers[bg_layer_index];
+    let gpu_target = bg_layer.gpu_target.as_ref()?;
+    state.gpu.as_mut().unwrap().uploader(
+        gpu_target.dmabuf_drm_dev,
+        bg_layer.width as u32,
+        bg_layer.height as u32,
+        gpu_target.drm_format_modifiers.clone(),
+    )
+        .map_err(|e| error!("Failed to obtain GPU uploader, \
+            falling back to shm: {e:#}"))
+        .ok()
+}
+
+/// Load the images of the random pools picked to be shown on outputs
+/// with their wallpapers in GPU memory, which drawing can not do
+pub fn load_picked_random_wallpapers(state: &mut State) {
+    for bg_layer_index in 0..state.background_layers.len() {
+        // Loop until one loads, as the failed ones are dropped
+        // from the pool and another one is picked
+        while let Some(index) = state.background_layers[bg_layer_index]
+            .random_gpu_pending.take()
+        {
+            let mut gpu_uploader = gpu_uploader(state, bg_layer_index);
+            let bg_layer = &mut state.background_layers[bg_layer_index];
+            match bg_layer.load_random(
+                index,
+                gpu_uploader.as_mut()
+                    .map(|uploader| (uploader, &state.dmabuf_state)),
+            ) {
+                Ok(wallpaper) => bg_layer.random_pool.insert(index, wallpaper),
+                Err(e) => bg_layer.drop_random(index, e),
+            }
+            bg_layer.redraw();
+        }
+    }
+}
+
 fn handle_dmabuf_feedback(
     state: &mut State,
     conn: &Connection,
@@ -1250,8 +2935,12 @@ fn handle_dmabuf_feedback(
         dmabuf_drm_dev,
         bg_layer.width as u32,
         bg_layer.height as u32,
-        drm_format_modifiers
+        drm_format_modifiers.clone()
     ).context("Failed to create GPU uploader")?;
+    bg_layer.gpu_target = Some(GpuTarget {
+        dmabuf_drm_dev,
+        drm_format_modifiers,
+    });
     if !bg_layer.workspace_backgrounds.is_empty() {
         debug!("DMA-BUF feedback changed, reloading wallpapers");
         bg_layer.workspace_backgrounds.clear();
@@ -1268,6 +2957,7 @@ fn wallpaper_dmabuf(
     height: i32,
     canon_path: PathBuf,
     canon_modified: u128,
+    processing: Processing,
 ) -> Rc<RefCell<Wallpaper>> {
     let GpuWallpaper {
         drm_format_modifier,
@@ -1299,6 +2989,8 @@ fn wallpaper_dmabuf(
         memory: Memory::Dmabuf { gpu_memory, params: Some(params) },
         canon_path,
         canon_modified,
+        processing,
+        animation: None,
     }))
 }
 